@@ -0,0 +1,489 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json;
+
+use tetris::*;
+
+/// The weight of each board feature when scoring a placement, see Bot::evaluate().
+/// Features that make the board worse, like holes, have negative weights.
+/// Weights can be saved to a JSON file, e.g. by the tune binary, and loaded by the bots.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Weights {
+    /// The sum of the height of every column
+    pub aggregate_height: f32,
+    /// The number of empty cells covered by a filled cell above
+    pub holes: f32,
+    /// The sum of the height differences between neighboring columns
+    pub bumpiness: f32,
+    /// The sum of each well's depth, counting deeper wells more, e.g. 1 + 2 + 3 for a depth of 3.
+    /// A well is an empty cell with filled cells (or walls) on both sides.
+    pub wells: f32,
+    /// The number of rows completed by the placement
+    pub rows_completed: f32,
+    /// The height where the shape was placed
+    pub landing_height: f32,
+    /// The number of times a filled cell is next to an empty cell within each row
+    pub row_transitions: f32,
+    /// The number of times a filled cell is next to an empty cell within each column
+    pub column_transitions: f32,
+}
+
+impl Weights {
+    /// The number of features, see also to_array()
+    pub const COUNT: usize = 8;
+
+    /// Weights similar to the El-Tetris player, based on Pierre Dellacherie's features
+    pub fn el_tetris() -> Weights {
+        Weights {
+            aggregate_height: 0.0,
+            holes: -7.9,
+            bumpiness: 0.0,
+            wells: -3.39,
+            rows_completed: 3.42,
+            landing_height: -4.5,
+            row_transitions: -3.22,
+            column_transitions: -9.35,
+        }
+    }
+
+    /// Returns every weight, in the order the fields are declared
+    pub fn to_array(&self) -> [f32; Weights::COUNT] {
+        [self.aggregate_height, self.holes, self.bumpiness, self.wells, self.rows_completed,
+            self.landing_height, self.row_transitions, self.column_transitions]
+    }
+
+    pub fn from_array(values: [f32; Weights::COUNT]) -> Weights {
+        Weights {
+            aggregate_height: values[0],
+            holes: values[1],
+            bumpiness: values[2],
+            wells: values[3],
+            rows_completed: values[4],
+            landing_height: values[5],
+            row_transitions: values[6],
+            column_transitions: values[7],
+        }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Weights> {
+        let text = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+}
+
+impl Default for Weights {
+    fn default() -> Weights {
+        Weights::el_tetris()
+    }
+}
+
+/// A Board is a simplified copy of the game board, only tracking which cells are filled,
+/// used to try out placements without changing the game. Each row is a bit mask, see also
+/// Tetris::get_row_bits().
+#[derive(Clone, PartialEq)]
+pub struct Board {
+    rows: [u16; ROW_COUNT as usize],
+}
+
+impl Board {
+    /// Copies the Fixed cells of a game
+    pub fn from_tetris(tetris: &Tetris) -> Board {
+        Board { rows: *tetris.get_row_bits() }
+    }
+
+    pub fn is_filled(&self, col: i32, row: i32) -> bool {
+        self.rows[row as usize] & (1 << col) != 0
+    }
+
+    /// Fills the cells of a placement, then removes any completed rows.
+    /// Returns the number of rows completed.
+    pub fn place(&mut self, placement: &Placement) -> u8 {
+        for point in placement.get_cells().iter() {
+            if point.y >= 0 {
+                self.rows[point.y as usize] |= 1 << point.x;
+            }
+        }
+        let mut result = 0;
+        let mut row = ROW_COUNT as usize;
+        while row > 0 {
+            row -= 1;
+            if self.rows[row] == FULL_ROW {
+                result += 1;
+                // bring all rows above row down one, and test the same row again
+                self.rows.copy_within(0..row, 1);
+                self.rows[0] = 0;
+                row += 1;
+            }
+        }
+        result
+    }
+
+    /// Measures the board, see also BoardAnalysis
+    pub fn analyze(&self) -> BoardAnalysis {
+        BoardAnalysis::new(&self.rows)
+    }
+}
+
+/// Explains why a placement is a good one, comparing the board it leaves behind with the board
+/// before it, e.g. to show beginners why a placement is recommended. Returns a short reason for
+/// each feature the placement improves or keeps, most important first, which may be none.
+pub fn explain_placement(tetris: &Tetris, placement: &Placement) -> Vec<&'static str> {
+    let board = Board::from_tetris(tetris);
+    let mut placed = board.clone();
+    let rows = placed.place(placement);
+    let (before, after) = (board.analyze(), placed.analyze());
+    let mut result = Vec::new();
+    if rows == 4 {
+        result.push("scores a tetris");
+    } else if rows > 0 {
+        result.push("completes rows");
+    }
+    // most placements leave the holes as they are, so they're only mentioned when uncovered
+    if after.hole_count() < before.hole_count() {
+        result.push("uncovers holes");
+    }
+    // a well at least 3 deep is kept for a long bar to complete several rows at once
+    let depths = before.well_depths();
+    if let Some(col) = (0..COL_COUNT as usize).max_by_key(|col| depths[*col]) {
+        let covered = placement.get_cells().iter().any(|point| point.x as usize == col);
+        if depths[col] >= 3 && rows == 0 && !covered {
+            result.push("keeps the well open");
+        }
+    }
+    if after.bumpiness() < before.bumpiness() {
+        result.push("flattens the stack");
+    }
+    result
+}
+
+/// A Planner chooses where to place the current shape, see also AiPlayer
+pub trait Planner {
+    /// Returns the placement to play for the current shape (or the held shape), or None if the game is over
+    fn choose(&mut self, tetris: &Tetris) -> Option<Placement>;
+}
+
+/// A Bot plays the game by choosing a placement for each shape, scoring the board that
+/// results from each possible placement with a set of Weights
+#[derive(Clone)]
+pub struct Bot {
+    weights: Weights,
+}
+
+impl Bot {
+    pub fn new(weights: Weights) -> Bot {
+        Bot { weights }
+    }
+
+    /// Scores the board that results from a placement, higher is better
+    pub fn evaluate(&self, board: &Board, placement: &Placement) -> f32 {
+        let mut board = board.clone();
+        let rows = board.place(placement);
+        self.evaluate_placement(placement, rows) + self.evaluate_board(&board)
+    }
+
+    /// Scores the features of a placement itself, rather than the board it leaves behind
+    pub fn evaluate_placement(&self, placement: &Placement, rows: u8) -> f32 {
+        let cells = placement.get_cells();
+        let landing_height = ROW_COUNT as f32 -
+            cells.iter().map(|point| point.y as f32).sum::<f32>() / POINT_COUNT as f32;
+        self.weights.rows_completed * rows as f32 + self.weights.landing_height * landing_height
+    }
+
+    /// Scores the shape of a board, higher is better
+    pub fn evaluate_board(&self, board: &Board) -> f32 {
+        let analysis = board.analyze();
+        let aggregate_height: u32 = analysis.column_heights().iter().sum();
+        let weights = &self.weights;
+        weights.aggregate_height * aggregate_height as f32 +
+            weights.holes * analysis.hole_count() as f32 +
+            weights.bumpiness * analysis.bumpiness() as f32 +
+            weights.wells * analysis.well_sum() as f32 +
+            weights.row_transitions * analysis.row_transitions() as f32 +
+            weights.column_transitions * analysis.column_transitions() as f32
+    }
+}
+
+impl Planner for Bot {
+    fn choose(&mut self, tetris: &Tetris) -> Option<Placement> {
+        let board = Board::from_tetris(tetris);
+        let mut result: Option<(f32, Placement)> = None;
+        for placement in tetris.get_placements(true) {
+            let score = self.evaluate(&board, &placement);
+            if result.as_ref().is_none_or(|&(best, _)| score > best) {
+                result = Some((score, placement));
+            }
+        }
+        result.map(|(_, placement)| placement)
+    }
+}
+
+/// A game reached by playing a sequence of placements, see also BeamSearch
+struct SearchNode {
+    tetris: Tetris,
+    /// The first placement of the sequence, which is the one actually played
+    first: Placement,
+    /// The sum of Bot::evaluate_placement() over the sequence
+    placement_score: f32,
+    /// placement_score plus the score of the resulting board, used to rank the nodes
+    score: f32,
+}
+
+/// A BeamSearch looks ahead through the preview queue and hold. Starting from the current game,
+/// every placement of every node is played out, and only the best nodes are kept at each depth.
+/// The placement leading to the best node at the deepest depth reached is played.
+#[derive(Clone)]
+pub struct BeamSearch {
+    bot: Bot,
+    /// The number of nodes kept at each depth
+    width: usize,
+    /// The number of placements to look ahead, at most PREVIEW_COUNT since later shapes aren't known yet
+    depth: usize,
+    /// The time allowed to choose a placement. The search stops at the last depth completed in time,
+    /// but always completes the first depth.
+    time_budget: Duration,
+    /// The number of threads expanding nodes at each depth, see also set_threads()
+    threads: usize,
+}
+
+impl BeamSearch {
+    pub fn new(bot: Bot, width: usize, depth: usize, time_budget: Duration) -> BeamSearch {
+        assert!(width > 0 && depth > 0);
+        BeamSearch {
+            bot,
+            width,
+            depth: depth.min(PREVIEW_COUNT as usize),
+            time_budget,
+            threads: thread::available_parallelism().map_or(1, |count| count.get()),
+        }
+    }
+
+    /// Returns a search with the default width, depth and time budget
+    pub fn with_weights(weights: Weights) -> BeamSearch {
+        BeamSearch::new(Bot::new(weights), 16, PREVIEW_COUNT as usize, Duration::from_millis(50))
+    }
+
+    pub fn get_width(&self) -> usize {
+        self.width
+    }
+
+    pub fn get_depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn get_time_budget(&self) -> Duration {
+        self.time_budget
+    }
+
+    pub fn get_threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Sets the number of threads used to search, every CPU core by default. The search
+    /// chooses the same placements however many threads it uses.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.max(1);
+    }
+
+    /// Plays every placement of each node, splitting the nodes between threads
+    fn expand_nodes(&self, nodes: &[SearchNode]) -> Vec<SearchNode> {
+        let expand_node = |node: &SearchNode| node.tetris.get_placements(true).iter()
+            .map(|placement| self.expand(node, placement))
+            .collect::<Vec<SearchNode>>();
+        let threads = self.threads.min(nodes.len());
+        if threads <= 1 {
+            return nodes.iter().flat_map(expand_node).collect();
+        }
+        let chunk_size = nodes.len().div_ceil(threads);
+        thread::scope(|scope| {
+            let handles: Vec<_> = nodes.chunks(chunk_size)
+                .map(|chunk| scope.spawn(move || chunk.iter().flat_map(expand_node).collect::<Vec<SearchNode>>()))
+                .collect();
+            // joined in order, so the nodes are in the same order as a single thread would expand them
+            handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+        })
+    }
+
+    /// Plays a placement on a copy of a game
+    fn expand(&self, node: &SearchNode, placement: &Placement) -> SearchNode {
+        let mut tetris = node.tetris.clone();
+        let mut rows = 0;
+        for input in placement.path.iter() {
+            if let Some(result) = tetris.apply_input(*input) {
+                rows = result.rows;
+            }
+        }
+        let placement_score = node.placement_score + self.bot.evaluate_placement(placement, rows);
+        // the board includes any garbage pushed onto it after the shape was fixed into place
+        let score = if tetris.get_game_over() {
+            f32::MIN
+        } else {
+            placement_score + self.bot.evaluate_board(&Board::from_tetris(&tetris))
+        };
+        SearchNode {
+            tetris,
+            first: node.first.clone(),
+            placement_score,
+            score,
+        }
+    }
+}
+
+impl Default for BeamSearch {
+    fn default() -> BeamSearch {
+        BeamSearch::with_weights(Weights::default())
+    }
+}
+
+impl Planner for BeamSearch {
+    fn choose(&mut self, tetris: &Tetris) -> Option<Placement> {
+        let start = Instant::now();
+        let mut beam: Vec<SearchNode> = Vec::new();
+        for placement in tetris.get_placements(true) {
+            let root = SearchNode {
+                tetris: tetris.clone(),
+                first: placement.clone(),
+                placement_score: 0.0,
+                score: 0.0,
+            };
+            beam.push(self.expand(&root, &placement));
+        }
+
+        for _ in 1..self.depth {
+            if start.elapsed() > self.time_budget {
+                break;
+            }
+            beam.sort_by(|a, b| b.score.total_cmp(&a.score));
+            beam.truncate(self.width);
+            let next_beam = self.expand_nodes(&beam);
+            // keep the previous depth if every node topped out
+            if next_beam.is_empty() {
+                break;
+            }
+            beam = next_beam;
+        }
+
+        beam.into_iter()
+            .max_by(|a, b| a.score.total_cmp(&b.score))
+            .map(|node| node.first)
+    }
+}
+
+/// An AiPlayer presses keys for a player, following the path to each placement chosen by a Planner
+pub struct AiPlayer {
+    planner: Box<dyn Planner>,
+    /// The remaining inputs of the path to the chosen placement
+    path: VecDeque<Input>,
+    /// The shape count when the placement was chosen, see also Tetris::get_shape_count()
+    shape_count: u32,
+}
+
+impl AiPlayer {
+    pub fn new(planner: Box<dyn Planner>) -> AiPlayer {
+        AiPlayer {
+            planner,
+            path: VecDeque::new(),
+            shape_count: 0,
+        }
+    }
+
+    /// Returns the next input to press, choosing a new placement when the current shape changes
+    pub fn next_input(&mut self, tetris: &Tetris) -> Option<Input> {
+        if tetris.get_game_over() {
+            self.path.clear();
+            return None;
+        }
+        if self.path.is_empty() || self.shape_count != tetris.get_shape_count() {
+            self.shape_count = tetris.get_shape_count();
+            self.path = self.planner.choose(tetris)
+                .map_or_else(VecDeque::new, |placement| placement.path.into_iter().collect());
+        }
+        self.path.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simulation::{GameStats, Simulation};
+    use std::env;
+    use std::process;
+
+    const I_SHAPE_INDEX: i32 = 6;
+
+    /// Plays a placement, returning the number of rows it completed
+    fn play(tetris: &mut Tetris, placement: &Placement) -> u8 {
+        let mut rows = 0;
+        for input in placement.path.iter() {
+            if let Some(result) = tetris.apply_input(*input) {
+                rows = result.rows;
+            }
+        }
+        rows
+    }
+
+    #[test]
+    fn the_bot_completes_rows_when_it_can() {
+        let mut bot = Bot::new(Weights::default());
+        let mut tetris = Tetris::with_board(&["XXXXXXXXX."; 4], -1, &[I_SHAPE_INDEX, SQUARE_SHAPE_INDEX]);
+        let placement = bot.choose(&tetris).unwrap();
+        assert_eq!(play(&mut tetris, &placement), 4);
+        assert!(tetris.get_row_bits().iter().all(|bits| *bits == 0));
+
+        let mut tetris = Tetris::with_board(&["XXXX..XXXX"], -1, &[SQUARE_SHAPE_INDEX, SQUARE_SHAPE_INDEX]);
+        let placement = bot.choose(&tetris).unwrap();
+        assert_eq!(play(&mut tetris, &placement), 1);
+    }
+
+    #[test]
+    fn the_bot_avoids_holes() {
+        // an O on the step would cover a hole, while the flat side takes it cleanly
+        let mut bot = Bot::new(Weights::default());
+        let tetris = Tetris::with_board(&["X.........", "XX........"], -1, &[SQUARE_SHAPE_INDEX, SQUARE_SHAPE_INDEX]);
+        let placement = bot.choose(&tetris).unwrap();
+        let mut board = Board::from_tetris(&tetris);
+        board.place(&placement);
+        assert_eq!(board.analyze().hole_count(), 0);
+    }
+
+    #[test]
+    fn looking_ahead_beats_a_single_placement() {
+        // rewarding nothing but completed rows, a single placement rarely sees a row it can
+        // complete, while the search finds the placements that set one up for the shapes coming
+        let mut weights = [0.0; Weights::COUNT];
+        weights[4] = 1.0;
+        let simulation = Simulation { max_pieces: 30, ..Simulation::default() };
+        let rows = |games: Vec<GameStats>| games.iter().map(|game| game.rows).sum::<u32>();
+        let greedy = rows(simulation.play_games(&mut Bot::new(Weights::from_array(weights)), 0..3));
+        let mut search = BeamSearch::new(Bot::new(Weights::from_array(weights)), 4, 2, Duration::from_secs(600));
+        let beam = rows(simulation.play_games(&mut search, 0..3));
+        assert!(beam > greedy, "{} rows looking ahead, {} without", beam, greedy);
+    }
+
+    #[test]
+    fn the_search_stops_at_the_first_depth_without_time() {
+        let mut search = BeamSearch::new(Bot::new(Weights::default()), 4, 3, Duration::from_secs(0));
+        let mut tetris = Tetris::with_board(&["XXXXXXXXX."; 4], -1, &[I_SHAPE_INDEX, SQUARE_SHAPE_INDEX]);
+        let placement = search.choose(&tetris).unwrap();
+        assert_eq!(play(&mut tetris, &placement), 4);
+    }
+
+    #[test]
+    fn weights_are_saved_and_loaded() {
+        let path = env::temp_dir().join(format!("tetris-piston-weights-{}.json", process::id()));
+        let weights = Weights::from_array([-0.5, -7.9, -0.25, -3.39, 3.42, -4.5, -3.22, -9.35]);
+        weights.save(&path).unwrap();
+        let loaded = Weights::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap().to_array(), weights.to_array());
+        assert!(Weights::load(&path).is_err());
+    }
+}
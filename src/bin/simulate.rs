@@ -0,0 +1,93 @@
+//! Plays many games with a bot, without a window, and prints the average results.
+//! For example, to play 100 sprints with the built-in AI:
+//!
+//! cargo run --release --bin simulate -- --games 100 --mode sprint
+
+extern crate serde_json;
+extern crate tetris_piston;
+
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use tetris_piston::ai::{BeamSearch, Bot, Planner, Weights};
+use tetris_piston::simulation::{Mode, Simulation, Summary};
+use tetris_piston::tbp::TbpBot;
+use tetris_piston::tetris::AttackTable;
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let arg_value = |name: &str| args.iter().position(|arg| arg == name)
+        .map(|index| args.get(index + 1).unwrap_or_else(|| panic!("Missing value for {}", name)).clone());
+    let number_value = |name: &str, default: u64| arg_value(name)
+        .map_or(default, |value| value.parse().unwrap_or_else(|_| panic!("{} must be a number", name)));
+
+    // --games N plays N games, with the seeds --seed, --seed + 1 and so on
+    let games = number_value("--games", 10);
+    let seed = number_value("--seed", 0);
+
+    let simulation = Simulation {
+        // --mode marathon plays until --pieces shapes are placed, --mode sprint until 40 rows are completed
+        mode: arg_value("--mode").map_or(Mode::Marathon, |name| Mode::from_name(&name)
+            .unwrap_or_else(|| panic!("Unknown mode '{}'", name))),
+        // --attack-table guideline or tetrio
+        attack_table: arg_value("--attack-table").map_or_else(AttackTable::guideline, |name| AttackTable::from_name(&name)
+            .unwrap_or_else(|| panic!("Unknown attack table '{}'", name))),
+        // --level n starts the games at level n, as it does in the game
+        starting_level: number_value("--level", 0) as u32,
+        max_pieces: number_value("--pieces", 1000) as u32,
+    };
+
+    // --bot greedy, --bot beam (the default) or --bot "command" for an external TBP bot,
+    // with --width, --depth and --time-budget milliseconds for the beam search. Without a time
+    // budget, the same seeds always give the same results however fast the machine is.
+    // --weights file uses weights saved by the tune binary
+    let weights = match arg_value("--weights") {
+        Some(path) => Weights::load(Path::new(&path)).unwrap_or_else(|error| panic!("Unable to load {}: {}", path, error)),
+        None => Weights::default(),
+    };
+    let bot_name = arg_value("--bot").unwrap_or_else(|| "beam".to_string());
+    // --threads N plays N games at once, every CPU core by default, or searches with N threads when
+    // playing a single game. External bots play one game at a time.
+    let threads = number_value("--threads", thread::available_parallelism().map_or(1, |count| count.get() as u64)) as usize;
+    let new_planner = || -> Box<dyn Planner> {
+        match bot_name.as_str() {
+            "greedy" => Box::new(Bot::new(weights.clone())),
+            "beam" => {
+                let default = BeamSearch::default();
+                let mut beam = BeamSearch::new(Bot::new(weights.clone()),
+                    number_value("--width", default.get_width() as u64) as usize,
+                    number_value("--depth", default.get_depth() as u64) as usize,
+                    arg_value("--time-budget").map_or(Duration::MAX, |_| Duration::from_millis(number_value("--time-budget", 0))));
+                // the threads search a single game, otherwise they're already busy playing games
+                beam.set_threads(if games > 1 { 1 } else { threads });
+                Box::new(beam)
+            },
+            command => {
+                let command: Vec<String> = command.split_whitespace().map(String::from).collect();
+                let mut bot = TbpBot::spawn(&command).unwrap_or_else(|error| panic!("Unable to start the bot: {}", error));
+                // a bot taking longer than --bot-timeout ms to suggest a move is stopped
+                if arg_value("--bot-timeout").is_some() {
+                    bot.set_timeout(Duration::from_millis(number_value("--bot-timeout", 0)));
+                }
+                Box::new(bot)
+            },
+        }
+    };
+
+    let results = match bot_name.as_str() {
+        "greedy" | "beam" => simulation.play_games_parallel(new_planner, seed..seed + games, threads),
+        _ => simulation.play_games(&mut *new_planner(), seed..seed + games),
+    };
+    let summary = Summary::from_games(&results);
+
+    // --format json (the default) or csv
+    match arg_value("--format").as_deref() {
+        Some("csv") => {
+            println!("{}", Summary::csv_header());
+            println!("{}", summary.to_csv());
+        },
+        Some("json") | None => println!("{}", serde_json::to_string_pretty(&summary).unwrap()),
+        Some(format) => panic!("Unknown format '{}'", format),
+    }
+}
@@ -0,0 +1,153 @@
+//! Tunes the weights of the built-in AI with a genetic algorithm. Each generation, every set of
+//! weights plays the same seeded games, and the best sets are kept and bred into the next
+//! generation. The best weights found so far are saved after every generation, to be loaded
+//! with --weights by the game or the simulate binary. For example:
+//!
+//! cargo run --release --bin tune -- --generations 30 --attack-table tetrio --fitness attack --output tetrio.json
+
+extern crate rand;
+extern crate tetris_piston;
+
+use std::path::Path;
+use std::thread;
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use tetris_piston::ai::{Bot, Weights};
+use tetris_piston::simulation::{GameStats, Mode, Simulation};
+use tetris_piston::tetris::AttackTable;
+
+/// The fraction of each generation carried over unchanged to the next
+const ELITE_FRACTION: f32 = 0.25;
+
+/// The chance of each weight being mutated in a new set of weights
+const MUTATION_RATE: f32 = 0.3;
+
+/// The largest change to a mutated weight, relative to the length of the weights
+const MUTATION_SIZE: f32 = 0.2;
+
+/// The number of sets of weights compared to pick each parent
+const TOURNAMENT_SIZE: usize = 3;
+
+/// A set of weights and how well they played in the current generation
+struct Candidate {
+    weights: [f32; Weights::COUNT],
+    fitness: f64,
+}
+
+/// Scales weights to a length of 1. The bot only compares scores, so the scale of the
+/// weights doesn't change how it plays.
+fn normalize(mut weights: [f32; Weights::COUNT]) -> [f32; Weights::COUNT] {
+    let length = weights.iter().map(|weight| weight * weight).sum::<f32>().sqrt();
+    if length > 0.0 {
+        for weight in weights.iter_mut() {
+            *weight /= length;
+        }
+    }
+    weights
+}
+
+/// Returns the best of a few candidates picked at random
+fn tournament<'a, R: Rng>(population: &'a [Candidate], rng: &mut R) -> &'a Candidate {
+    (0..TOURNAMENT_SIZE)
+        .map(|_| &population[rng.gen_range(0, population.len())])
+        .max_by(|a, b| a.fitness.total_cmp(&b.fitness))
+        .unwrap()
+}
+
+/// Breeds two parents, averaging their weights in proportion to their fitness, then mutating
+fn breed<R: Rng>(a: &Candidate, b: &Candidate, rng: &mut R) -> [f32; Weights::COUNT] {
+    let total = a.fitness + b.fitness;
+    let share = if total > 0.0 { (a.fitness / total) as f32 } else { 0.5 };
+    let mut result = [0f32; Weights::COUNT];
+    for (index, weight) in result.iter_mut().enumerate() {
+        *weight = a.weights[index] * share + b.weights[index] * (1.0 - share);
+        if rng.gen::<f32>() < MUTATION_RATE {
+            *weight += rng.gen_range(-MUTATION_SIZE, MUTATION_SIZE);
+        }
+    }
+    normalize(result)
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let arg_value = |name: &str| args.iter().position(|arg| arg == name)
+        .map(|index| args.get(index + 1).unwrap_or_else(|| panic!("Missing value for {}", name)).clone());
+    let number_value = |name: &str, default: u64| arg_value(name)
+        .map_or(default, |value| value.parse().unwrap_or_else(|_| panic!("{} must be a number", name)));
+
+    let population_size = number_value("--population", 32) as usize;
+    if population_size == 0 {
+        panic!("--population must be at least 1");
+    }
+    let generations = number_value("--generations", 20);
+    // every set of weights plays --games games of up to --pieces shapes each generation
+    let games = number_value("--games", 8);
+    let output = arg_value("--output").unwrap_or_else(|| "weights.json".to_string());
+    let threads = number_value("--threads", thread::available_parallelism().map_or(1, |count| count.get() as u64)) as usize;
+    // --fitness rows, score, attack or pieces is averaged over the games
+    let fitness_name = arg_value("--fitness").unwrap_or_else(|| "rows".to_string());
+    let fitness: fn(&GameStats) -> f64 = match fitness_name.as_str() {
+        "rows" => |game| game.rows as f64,
+        "score" => |game| game.score as f64,
+        "attack" => |game| game.attack as f64,
+        "pieces" => |game| game.pieces as f64,
+        name => panic!("Unknown fitness '{}'", name),
+    };
+
+    let simulation = Simulation {
+        mode: Mode::Marathon,
+        attack_table: arg_value("--attack-table").map_or_else(AttackTable::guideline, |name| AttackTable::from_name(&name)
+            .unwrap_or_else(|| panic!("Unknown attack table '{}'", name))),
+        starting_level: 0,
+        max_pieces: number_value("--pieces", 500) as u32,
+    };
+
+    // the first generation starts from the default weights, or --weights file, and random weights
+    let mut rng = StdRng::seed_from_u64(number_value("--seed", 0));
+    let start = match arg_value("--weights") {
+        Some(path) => Weights::load(Path::new(&path)).unwrap_or_else(|error| panic!("Unable to load {}: {}", path, error)),
+        None => Weights::default(),
+    };
+    let mut population: Vec<[f32; Weights::COUNT]> = vec![normalize(start.to_array())];
+    while population.len() < population_size {
+        let mut weights = [0f32; Weights::COUNT];
+        for weight in weights.iter_mut() {
+            *weight = rng.gen_range(-1.0, 1.0);
+        }
+        population.push(normalize(weights));
+    }
+
+    for generation in 0..generations {
+        // every set of weights plays the same games, new games each generation
+        let seeds = generation * games..(generation + 1) * games;
+        let chunk_size = population.len().div_ceil(threads.max(1));
+        let mut candidates: Vec<Candidate> = thread::scope(|scope| {
+            let handles: Vec<_> = population.chunks(chunk_size).map(|chunk| {
+                let (simulation, seeds) = (&simulation, seeds.clone());
+                scope.spawn(move || chunk.iter().map(|weights| {
+                    let mut bot = Bot::new(Weights::from_array(*weights));
+                    let results = simulation.play_games(&mut bot, seeds.clone());
+                    let fitness = results.iter().map(fitness).sum::<f64>() / results.len().max(1) as f64;
+                    Candidate { weights: *weights, fitness }
+                }).collect::<Vec<Candidate>>())
+            }).collect();
+            handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+        });
+        candidates.sort_by(|a, b| b.fitness.total_cmp(&a.fitness));
+
+        let best = Weights::from_array(candidates[0].weights);
+        best.save(Path::new(&output)).unwrap_or_else(|error| panic!("Unable to save {}: {}", output, error));
+        println!("Generation {}: best {} {:.2}, average {:.2}, saved to {}", generation + 1, fitness_name,
+            candidates[0].fitness, candidates.iter().map(|candidate| candidate.fitness).sum::<f64>() / candidates.len() as f64,
+            output);
+
+        let elites = ((population_size as f32 * ELITE_FRACTION) as usize).max(1);
+        population = candidates.iter().take(elites).map(|candidate| candidate.weights).collect();
+        while population.len() < population_size {
+            let (a, b) = (tournament(&candidates, &mut rng), tournament(&candidates, &mut rng));
+            population.push(breed(a, b, &mut rng));
+        }
+    }
+}
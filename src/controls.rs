@@ -0,0 +1,379 @@
+use std::collections::BTreeMap;
+
+use tetris::*;
+
+/// Something a player can do by pressing a key or a gamepad button, see also Bindings
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    SoftDrop,
+    HardDrop,
+    RotateCW,
+    RotateCCW,
+    Rotate180,
+    Hold,
+    NewGame,
+    /// Pauses or resumes every local game
+    Pause,
+    LevelDown,
+    LevelUp,
+    /// Switches the AI on or off
+    ToggleAi,
+    /// Switches the perfect clear overlay on or off
+    PerfectClears,
+    /// Switches the opener trainer on or off
+    Openers,
+    /// Switches flashing the shortest path after a finesse fault on or off
+    FinesseFlash,
+    /// Switches the placement hint on or off
+    Hints,
+    /// Shows the screen for binding new keys to the player actions
+    Rebind,
+    /// Shows or hides the high-score table
+    HighScores,
+}
+
+impl Action {
+    /// The actions that move a player's shape, which each player binds their own keys to
+    pub const PLAYER_ACTIONS: [Action; 8] = [Action::MoveLeft, Action::MoveRight, Action::SoftDrop,
+        Action::HardDrop, Action::RotateCW, Action::RotateCCW, Action::Rotate180, Action::Hold];
+
+    /// Returns the game Input for a player action, or None for the actions that don't apply to a
+    /// single game
+    pub fn input(self) -> Option<Input> {
+        match self {
+            Action::MoveLeft => Some(Input::Left),
+            Action::MoveRight => Some(Input::Right),
+            Action::SoftDrop => Some(Input::SoftDrop),
+            Action::HardDrop => Some(Input::HardDrop),
+            Action::RotateCW => Some(Input::RotateClockwise),
+            Action::RotateCCW => Some(Input::RotateCounterClockwise),
+            Action::Rotate180 => Some(Input::Rotate180),
+            Action::Hold => Some(Input::Hold),
+            _ => None,
+        }
+    }
+
+    /// Returns a name for the action to show to players
+    pub fn get_name(self) -> &'static str {
+        match self {
+            Action::MoveLeft => "Move left",
+            Action::MoveRight => "Move right",
+            Action::SoftDrop => "Soft drop",
+            Action::HardDrop => "Hard drop",
+            Action::RotateCW => "Rotate clockwise",
+            Action::RotateCCW => "Rotate counter-clockwise",
+            Action::Rotate180 => "Rotate 180",
+            Action::Hold => "Hold",
+            Action::NewGame => "New game",
+            Action::Pause => "Pause",
+            Action::LevelDown => "Level down",
+            Action::LevelUp => "Level up",
+            Action::ToggleAi => "AI",
+            Action::PerfectClears => "Perfect clears",
+            Action::Openers => "Openers",
+            Action::FinesseFlash => "Finesse",
+            Action::Hints => "Hints",
+            Action::Rebind => "Rebind keys",
+            Action::HighScores => "High scores",
+        }
+    }
+}
+
+/// Bindings map keys to actions. Any number of keys can be bound to an action, but each key
+/// does a single action. The key type is left to the caller, e.g. the keyboard keys of the window
+/// library. Bindings are saved as JSON, with an array of keys for each action.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(transparent)]
+pub struct Bindings<K: Ord> {
+    actions: BTreeMap<Action, Vec<K>>,
+}
+
+impl<K: Copy + Ord> Bindings<K> {
+    pub fn new() -> Bindings<K> {
+        Bindings { actions: BTreeMap::new() }
+    }
+
+    /// Binds keys to an action in place of its current keys. The keys are unbound from any other action.
+    pub fn bind(&mut self, action: Action, keys: &[K]) {
+        for other in self.actions.values_mut() {
+            other.retain(|key| !keys.contains(key));
+        }
+        self.actions.insert(action, keys.to_vec());
+    }
+
+    /// Binds the keys of every action in other, keeping the keys of the actions other doesn't have
+    pub fn merge(&mut self, other: &Bindings<K>) {
+        for (action, keys) in other.actions.iter() {
+            self.bind(*action, keys);
+        }
+    }
+
+    /// Returns the keys bound to an action
+    pub fn get_keys(&self, action: Action) -> &[K] {
+        self.actions.get(&action).map_or(&[], |keys| keys.as_slice())
+    }
+
+    /// Returns the action a key is bound to
+    pub fn action(&self, key: K) -> Option<Action> {
+        self.actions.iter()
+            .find(|(_, keys)| keys.contains(&key))
+            .map(|(action, _)| *action)
+    }
+
+    /// Returns the keys that are bound both here and in other, which would do two actions at once
+    pub fn shared_keys(&self, other: &Bindings<K>) -> Vec<K> {
+        self.actions.values().flatten()
+            .filter(|key| other.action(**key).is_some())
+            .cloned()
+            .collect()
+    }
+}
+
+impl<K: Copy + Ord> Default for Bindings<K> {
+    fn default() -> Bindings<K> {
+        Bindings::new()
+    }
+}
+
+/// A direction pressed on a gamepad's D-pad
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+/// A stick axis of a gamepad, which presses one action when pushed towards its negative end and
+/// another towards its positive end
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+pub struct AxisBinding {
+    /// The number of the axis, e.g. 0 for the left stick's horizontal axis on most gamepads
+    pub axis: u8,
+    pub negative: Option<Action>,
+    pub positive: Option<Action>,
+}
+
+/// The buttons, D-pad and sticks of a gamepad bound to actions. The defaults follow the button
+/// numbers of an Xbox style gamepad.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct GamepadBindings {
+    pub buttons: Bindings<u8>,
+    pub d_pad: Bindings<Direction>,
+    pub axes: Vec<AxisBinding>,
+    /// How far a stick is pushed from the centre before it presses an action, from 0 to 1
+    pub dead_zone: f64,
+}
+
+impl Default for GamepadBindings {
+    fn default() -> GamepadBindings {
+        let mut buttons = Bindings::new();
+        buttons.bind(Action::RotateCCW, &[0]);
+        buttons.bind(Action::RotateCW, &[1]);
+        buttons.bind(Action::Rotate180, &[2]);
+        buttons.bind(Action::Hold, &[3, 4, 5]);
+        buttons.bind(Action::NewGame, &[6]);
+        buttons.bind(Action::Pause, &[7]);
+        let mut d_pad = Bindings::new();
+        d_pad.bind(Action::MoveLeft, &[Direction::Left]);
+        d_pad.bind(Action::MoveRight, &[Direction::Right]);
+        d_pad.bind(Action::SoftDrop, &[Direction::Down]);
+        d_pad.bind(Action::HardDrop, &[Direction::Up]);
+        GamepadBindings {
+            buttons,
+            d_pad,
+            // pushing the left stick up doesn't hard drop, since it's too easily done by accident
+            axes: vec![AxisBinding { axis: 0, negative: Some(Action::MoveLeft), positive: Some(Action::MoveRight) },
+                AxisBinding { axis: 1, negative: None, positive: Some(Action::SoftDrop) }],
+            dead_zone: 0.5,
+        }
+    }
+}
+
+/// A GamepadState follows the sticks and D-pad of a single gamepad, turning their movements into
+/// actions pressed and released like keys. Each change returns the actions released, as
+/// (action, false), followed by the actions pressed, as (action, true).
+#[derive(Clone, Debug, Default)]
+pub struct GamepadState {
+    /// The action held by each axis pushed past the dead zone
+    axes: Vec<(u8, Action)>,
+    /// The directions pressed on the D-pad
+    directions: Vec<Direction>,
+    /// The actions held by the directions pressed on the D-pad
+    d_pad: Vec<Action>,
+}
+
+impl GamepadState {
+    pub fn new() -> GamepadState {
+        GamepadState::default()
+    }
+
+    /// Moves a stick axis to a position from -1 to 1
+    pub fn move_axis(&mut self, bindings: &GamepadBindings, axis: u8, position: f64) -> Vec<(Action, bool)> {
+        let pushed = bindings.axes.iter()
+            .filter(|binding| binding.axis == axis)
+            .find_map(|binding| if position <= -bindings.dead_zone {
+                binding.negative
+            } else if position >= bindings.dead_zone {
+                binding.positive
+            } else {
+                None
+            });
+        let held = self.axes.iter().position(|(held_axis, _)| *held_axis == axis)
+            .map(|index| self.axes.remove(index).1);
+        let mut result = Vec::new();
+        if held != pushed {
+            result.extend(held.map(|action| (action, false)));
+            result.extend(pushed.map(|action| (action, true)));
+        }
+        if let Some(action) = pushed {
+            self.axes.push((axis, action));
+        }
+        result
+    }
+
+    /// Sets the directions pressed on the D-pad, which are none when it's centred
+    pub fn set_d_pad(&mut self, bindings: &GamepadBindings, directions: &[Direction]) -> Vec<(Action, bool)> {
+        let actions: Vec<Action> = directions.iter().filter_map(|direction| bindings.d_pad.action(*direction)).collect();
+        let mut result: Vec<(Action, bool)> = self.d_pad.iter()
+            .filter(|action| !actions.contains(action))
+            .map(|action| (*action, false))
+            .collect();
+        result.extend(actions.iter().filter(|action| !self.d_pad.contains(action)).map(|action| (*action, true)));
+        self.directions = directions.to_vec();
+        self.d_pad = actions;
+        result
+    }
+
+    /// Presses or releases a single direction of the D-pad, for gamepads that report it as buttons
+    pub fn press_d_pad(&mut self, bindings: &GamepadBindings, direction: Direction, pressed: bool) -> Vec<(Action, bool)> {
+        let mut directions: Vec<Direction> = self.directions.iter().filter(|held| **held != direction).cloned().collect();
+        if pressed {
+            directions.push(direction);
+        }
+        self.set_d_pad(bindings, &directions)
+    }
+
+    /// Releases every action, e.g. when the gamepad is disconnected
+    pub fn release_all(&mut self) -> Vec<(Action, bool)> {
+        let result = self.axes.iter().map(|(_, action)| (*action, false))
+            .chain(self.d_pad.iter().map(|action| (*action, false)))
+            .collect();
+        *self = GamepadState::default();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_keys_are_found_in_either_bindings() {
+        let mut one = Bindings::new();
+        one.bind(Action::MoveLeft, &['a', 'j']);
+        one.bind(Action::HardDrop, &[' ']);
+        let mut two = Bindings::new();
+        two.bind(Action::NewGame, &['n', 'j']);
+        assert_eq!(one.shared_keys(&two), vec!['j']);
+        assert_eq!(two.shared_keys(&one), vec!['j']);
+        two.bind(Action::NewGame, &['n']);
+        assert_eq!(one.shared_keys(&two), vec![]);
+    }
+
+    #[test]
+    fn binding_a_key_unbinds_it_from_other_actions() {
+        let mut bindings = Bindings::new();
+        bindings.bind(Action::MoveLeft, &['a']);
+        bindings.bind(Action::MoveRight, &['a', 'd']);
+        assert_eq!(bindings.get_keys(Action::MoveLeft), &[] as &[char]);
+        assert_eq!(bindings.action('a'), Some(Action::MoveRight));
+    }
+
+    #[test]
+    fn buttons_press_their_actions() {
+        let bindings = GamepadBindings::default();
+        assert_eq!(bindings.buttons.action(0), Some(Action::RotateCCW));
+        assert_eq!(bindings.buttons.action(1), Some(Action::RotateCW));
+        assert_eq!(bindings.buttons.action(4), Some(Action::Hold));
+        assert_eq!(bindings.buttons.action(7), Some(Action::Pause));
+        assert_eq!(bindings.buttons.action(20), None);
+    }
+
+    #[test]
+    fn d_pad_presses_and_releases_directions() {
+        let bindings = GamepadBindings::default();
+        let mut state = GamepadState::new();
+        assert_eq!(state.press_d_pad(&bindings, Direction::Left, true), vec![(Action::MoveLeft, true)]);
+        // holding a direction doesn't press it again
+        assert_eq!(state.press_d_pad(&bindings, Direction::Left, true), vec![]);
+        assert_eq!(state.press_d_pad(&bindings, Direction::Down, true), vec![(Action::SoftDrop, true)]);
+        assert_eq!(state.press_d_pad(&bindings, Direction::Left, false), vec![(Action::MoveLeft, false)]);
+        // rolling from one diagonal to another releases before it presses
+        assert_eq!(state.set_d_pad(&bindings, &[Direction::Right, Direction::Down]), vec![(Action::MoveRight, true)]);
+        assert_eq!(state.set_d_pad(&bindings, &[Direction::Left, Direction::Up]),
+            vec![(Action::MoveRight, false), (Action::SoftDrop, false), (Action::MoveLeft, true), (Action::HardDrop, true)]);
+        assert_eq!(state.set_d_pad(&bindings, &[]), vec![(Action::MoveLeft, false), (Action::HardDrop, false)]);
+    }
+
+    #[test]
+    fn axes_press_past_the_dead_zone() {
+        let bindings = GamepadBindings::default();
+        let mut state = GamepadState::new();
+        assert_eq!(state.move_axis(&bindings, 0, -0.3), vec![]);
+        assert_eq!(state.move_axis(&bindings, 0, -0.6), vec![(Action::MoveLeft, true)]);
+        // moving further, or back but still past the dead zone, keeps the action held
+        assert_eq!(state.move_axis(&bindings, 0, -1.0), vec![]);
+        assert_eq!(state.move_axis(&bindings, 0, -0.5), vec![]);
+        // flicking the stick across releases one end and presses the other
+        assert_eq!(state.move_axis(&bindings, 0, 0.8), vec![(Action::MoveLeft, false), (Action::MoveRight, true)]);
+        assert_eq!(state.move_axis(&bindings, 0, 0.1), vec![(Action::MoveRight, false)]);
+        // the vertical axis only soft drops
+        assert_eq!(state.move_axis(&bindings, 1, -1.0), vec![]);
+        assert_eq!(state.move_axis(&bindings, 1, 1.0), vec![(Action::SoftDrop, true)]);
+        // unbound axes do nothing
+        assert_eq!(state.move_axis(&bindings, 3, 1.0), vec![]);
+    }
+
+    #[test]
+    fn dead_zone_comes_from_the_bindings() {
+        let bindings = GamepadBindings { dead_zone: 0.2, ..GamepadBindings::default() };
+        let mut state = GamepadState::new();
+        assert_eq!(state.move_axis(&bindings, 0, 0.3), vec![(Action::MoveRight, true)]);
+    }
+
+    #[test]
+    fn release_all_releases_every_held_action() {
+        let bindings = GamepadBindings::default();
+        let mut state = GamepadState::new();
+        state.move_axis(&bindings, 1, 0.9);
+        state.press_d_pad(&bindings, Direction::Right, true);
+        assert_eq!(state.release_all(), vec![(Action::SoftDrop, false), (Action::MoveRight, false)]);
+        assert_eq!(state.release_all(), vec![]);
+        // after releasing, holding the D-pad again presses again
+        assert_eq!(state.press_d_pad(&bindings, Direction::Right, true), vec![(Action::MoveRight, true)]);
+    }
+
+    #[test]
+    fn held_gamepad_actions_repeat_like_keys() {
+        let mut auto_repeat = ::handling::AutoRepeat::new(::handling::Handling::default());
+        let bindings = GamepadBindings::default();
+        let mut state = GamepadState::new();
+        for (action, pressed) in state.move_axis(&bindings, 0, 1.0) {
+            assert!(auto_repeat.press(action.input().unwrap()) && pressed);
+        }
+        // before the delayed auto shift nothing repeats, after it the held stick moves the shape
+        assert_eq!(auto_repeat.update(0.1, 1.0), vec![]);
+        let repeated = auto_repeat.update(0.2, 1.0);
+        assert!(repeated.len() > 1);
+        assert!(repeated.iter().all(|input| *input == Input::Right));
+        for (action, pressed) in state.move_axis(&bindings, 0, 0.0) {
+            assert!(!pressed);
+            auto_repeat.release(action.input().unwrap());
+        }
+        assert_eq!(auto_repeat.update(1.0, 1.0), vec![]);
+    }
+}
@@ -0,0 +1,361 @@
+use ai::{Board, Bot, Weights};
+use tetris::*;
+
+/// Which actions an agent chooses from, see also Action
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ActionSpace {
+    /// Each step presses a single key
+    Inputs,
+    /// Each step fixes the current shape into one of the placements in the observation
+    Placements,
+}
+
+/// An action taken by an agent in a single step
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Action {
+    /// Presses a key, for ActionSpace::Inputs
+    Input(Input),
+    /// Plays the placement at this index of Observation::placements, for ActionSpace::Placements
+    Placement(usize),
+}
+
+/// The keys of ActionSpace::Inputs, e.g. for an agent that numbers its actions
+pub const INPUTS: [Input; 9] = [
+    Input::Left, Input::Right, Input::RotateClockwise, Input::RotateCounterClockwise, Input::Rotate180,
+    Input::SoftDrop, Input::HardDrop, Input::Hold, Input::Tick,
+];
+
+/// The reward given for each step. Only steps that fix a shape into place are rewarded,
+/// apart from topping out.
+#[derive(Clone, Debug)]
+pub struct RewardConfig {
+    /// The reward for completing 0 to 4 rows at once
+    pub rows: [f32; 5],
+    /// The reward for each line of attack, see also AttackTable
+    pub attack: f32,
+    /// The reward for each shape fixed into place, rewarding survival
+    pub piece: f32,
+    /// The reward when the game ends by topping out, normally negative
+    pub top_out: f32,
+    /// If set, the change in Bot::evaluate_board() with these weights is added to the reward,
+    /// rewarding shapes that improve the board
+    pub board_weights: Option<Weights>,
+}
+
+impl Default for RewardConfig {
+    fn default() -> RewardConfig {
+        RewardConfig {
+            rows: [0.0, 1.0, 3.0, 5.0, 8.0],
+            attack: 0.0,
+            piece: 0.01,
+            top_out: -5.0,
+            board_weights: None,
+        }
+    }
+}
+
+/// The settings of an Environment
+#[derive(Clone, Debug)]
+pub struct EnvConfig {
+    pub action_space: ActionSpace,
+    pub rewards: RewardConfig,
+    pub attack_table: AttackTable,
+    pub starting_level: u32,
+    /// With ActionSpace::Inputs, gravity advances the shape a row after this many steps,
+    /// or never if 0
+    pub steps_per_tick: u32,
+    /// The episode is cut short once this many shapes are fixed into place, or never if 0
+    pub max_pieces: u32,
+}
+
+impl Default for EnvConfig {
+    fn default() -> EnvConfig {
+        EnvConfig {
+            action_space: ActionSpace::Placements,
+            rewards: RewardConfig::default(),
+            attack_table: AttackTable::guideline(),
+            starting_level: 0,
+            steps_per_tick: 10,
+            max_pieces: 0,
+        }
+    }
+}
+
+/// What an agent sees of the game after each step
+#[derive(Clone, Debug)]
+pub struct Observation {
+    /// The Fixed cells, 1 if filled or 0 if empty, ROW_COUNT rows of COL_COUNT cells starting with
+    /// the top row. The current shape isn't included.
+    pub grid: Vec<u8>,
+    /// The current shape index into the SHAPES const
+    pub shape_index: i32,
+    /// The number of clockwise rotations of the current shape from its starting position
+    pub rotation: u8,
+    /// The column and row of the current shape
+    pub col: i32,
+    pub row: i32,
+    /// The next shape indices, see also Tetris::get_next_queue()
+    pub queue: Vec<i32>,
+    /// The held shape index, or -1 if no shape is held
+    pub hold_shape_index: i32,
+    pub can_hold: bool,
+    /// The rows of garbage waiting to be pushed onto the board
+    pub pending_garbage: u32,
+    /// With ActionSpace::Placements, the placements Action::Placement chooses from,
+    /// including the placements of the held shape. Otherwise empty.
+    pub placements: Vec<Placement>,
+}
+
+impl Observation {
+    /// Returns the grid as one number per row starting with the top row, where bit n is set
+    /// if the cell in column n is filled
+    pub fn grid_bits(&self) -> Vec<u16> {
+        self.grid.chunks(COL_COUNT as usize)
+            .map(|row| row.iter().enumerate().fold(0, |bits, (col, cell)| bits | ((*cell as u16) << col)))
+            .collect()
+    }
+}
+
+/// Details of a step, apart from the reward
+#[derive(Clone, Debug, Default)]
+pub struct StepInfo {
+    /// The result of fixing a shape into place, if the step did
+    pub lock: Option<LockResult>,
+    /// True if the action was ignored, because it doesn't belong to the action space or the
+    /// placement index is out of range
+    pub invalid_action: bool,
+    /// True if the episode was cut short by EnvConfig::max_pieces rather than topping out
+    pub truncated: bool,
+}
+
+/// An Environment wraps a game for training agents, in the style of a gym environment:
+/// reset() starts an episode, then step() plays one action at a time until done.
+pub struct Environment {
+    tetris: Tetris,
+    config: EnvConfig,
+    /// Scores the board for RewardConfig::board_weights
+    bot: Option<Bot>,
+    /// The score of the board after the last shape was fixed into place
+    board_score: f32,
+    /// The steps since gravity last advanced the shape
+    steps_since_tick: u32,
+    /// The placements offered in the last observation
+    placements: Vec<Placement>,
+}
+
+impl Environment {
+    pub fn new(config: EnvConfig) -> Environment {
+        let mut tetris = Tetris::new();
+        tetris.set_attack_table(config.attack_table.clone());
+        tetris.set_starting_level(config.starting_level);
+        Environment {
+            tetris,
+            bot: config.rewards.board_weights.clone().map(Bot::new),
+            config,
+            board_score: 0.0,
+            steps_since_tick: 0,
+            placements: Vec::new(),
+        }
+    }
+
+    /// Returns the game, e.g. to render it
+    pub fn get_tetris(&self) -> &Tetris {
+        &self.tetris
+    }
+
+    /// Returns the number of actions to choose from: every key of INPUTS, or the placements in
+    /// the last observation
+    pub fn action_count(&self) -> usize {
+        match self.config.action_space {
+            ActionSpace::Inputs => INPUTS.len(),
+            ActionSpace::Placements => self.placements.len(),
+        }
+    }
+
+    /// Starts a new episode. The same seed and actions always play the same game.
+    pub fn reset(&mut self, seed: u64) -> Observation {
+        self.tetris.end_game();
+        self.tetris.set_seed(seed);
+        self.tetris.start_game();
+        self.steps_since_tick = 0;
+        self.board_score = self.evaluate_board();
+        self.observe()
+    }
+
+    /// Plays an action, returning the observation after the action, the reward, whether the
+    /// episode is done, and details of the step
+    pub fn step(&mut self, action: Action) -> (Observation, f32, bool, StepInfo) {
+        let mut info = StepInfo::default();
+        if self.tetris.get_game_over() {
+            info.invalid_action = true;
+            return (self.observe(), 0.0, true, info);
+        }
+
+        match (self.config.action_space, action) {
+            (ActionSpace::Inputs, Action::Input(input)) => {
+                info.lock = self.tetris.apply_input(input);
+                self.steps_since_tick += 1;
+                if input == Input::HardDrop || info.lock.is_some() {
+                    self.steps_since_tick = 0;
+                } else if self.config.steps_per_tick > 0 && self.steps_since_tick >= self.config.steps_per_tick {
+                    self.steps_since_tick = 0;
+                    info.lock = self.tetris.tick();
+                }
+            },
+            (ActionSpace::Placements, Action::Placement(index)) if index < self.placements.len() => {
+                for input in self.placements[index].path.clone() {
+                    if let Some(result) = self.tetris.apply_input(input) {
+                        info.lock = Some(result);
+                    }
+                }
+            },
+            _ => info.invalid_action = true,
+        }
+
+        let mut reward = 0.0;
+        if let Some(result) = info.lock {
+            let rewards = &self.config.rewards;
+            reward += rewards.rows[result.rows as usize] + rewards.attack * result.attack as f32 + rewards.piece;
+            if self.bot.is_some() {
+                let board_score = self.evaluate_board();
+                reward += board_score - self.board_score;
+                self.board_score = board_score;
+            }
+        }
+        if self.tetris.get_game_over() {
+            reward += self.config.rewards.top_out;
+        } else if self.config.max_pieces > 0 && self.tetris.get_shape_count() >= self.config.max_pieces {
+            info.truncated = true;
+        }
+        let done = self.tetris.get_game_over() || info.truncated;
+        (self.observe(), reward, done, info)
+    }
+
+    fn evaluate_board(&self) -> f32 {
+        match self.bot {
+            Some(ref bot) => bot.evaluate_board(&Board::from_tetris(&self.tetris)),
+            None => 0.0,
+        }
+    }
+
+    fn observe(&mut self) -> Observation {
+        self.placements = if self.config.action_space == ActionSpace::Placements {
+            self.tetris.get_placements(true)
+        } else {
+            Vec::new()
+        };
+        let mut grid = Vec::with_capacity(ROW_COUNT as usize * COL_COUNT as usize);
+        for row in 0..ROW_COUNT as i32 {
+            for col in 0..COL_COUNT as i32 {
+                grid.push((self.tetris.get_grid_cell(col, row).cell_type == GridCellType::Fixed) as u8);
+            }
+        }
+        Observation {
+            grid,
+            shape_index: self.tetris.get_shape_index(),
+            rotation: self.tetris.get_rotation(),
+            col: self.tetris.get_col(),
+            row: self.tetris.get_row(),
+            queue: self.tetris.get_next_queue().iter().cloned().collect(),
+            hold_shape_index: self.tetris.get_hold_shape_index(),
+            can_hold: self.tetris.get_can_hold(),
+            pending_garbage: self.tetris.get_pending_garbage(),
+            placements: self.placements.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const I_SHAPE_INDEX: i32 = 6;
+
+    #[test]
+    fn the_same_seed_plays_the_same_game() {
+        let mut first = Environment::new(EnvConfig::default());
+        let mut second = Environment::new(EnvConfig::default());
+        let (a, b) = (first.reset(7), second.reset(7));
+        assert_eq!((a.grid, a.shape_index, a.queue), (b.grid, b.shape_index, b.queue));
+        for step in 0..30 {
+            let action = Action::Placement(step % first.action_count());
+            let (a, reward_a, done_a, _) = first.step(action);
+            let (b, reward_b, done_b, _) = second.step(action);
+            assert_eq!((a.grid, a.shape_index, a.queue, a.hold_shape_index), (b.grid, b.shape_index, b.queue, b.hold_shape_index));
+            assert_eq!((reward_a, done_a), (reward_b, done_b));
+            if done_a {
+                break;
+            }
+        }
+        // resetting starts the same game again
+        let (a, b) = (first.reset(7), Environment::new(EnvConfig::default()).reset(7));
+        assert_eq!((a.grid, a.shape_index, a.queue), (b.grid, b.shape_index, b.queue));
+    }
+
+    #[test]
+    fn actions_outside_the_action_space_are_invalid() {
+        let mut env = Environment::new(EnvConfig::default());
+        let observation = env.reset(1);
+        assert!(env.action_count() > 0);
+        assert_eq!(env.action_count(), observation.placements.len());
+        assert!(env.step(Action::Placement(env.action_count())).3.invalid_action);
+        assert!(env.step(Action::Input(Input::Left)).3.invalid_action);
+
+        let mut env = Environment::new(EnvConfig { action_space: ActionSpace::Inputs, ..EnvConfig::default() });
+        assert!(env.reset(1).placements.is_empty());
+        assert_eq!(env.action_count(), INPUTS.len());
+        assert!(env.step(Action::Placement(0)).3.invalid_action);
+        let (_, reward, done, info) = env.step(Action::Input(Input::HardDrop));
+        assert!(!info.invalid_action && info.lock.is_some() && !done);
+        assert_eq!(reward, RewardConfig::default().piece);
+    }
+
+    #[test]
+    fn rows_attack_and_pieces_are_rewarded() {
+        let rewards = RewardConfig { rows: [0.0, 10.0, 30.0, 50.0, 80.0], attack: 2.0, piece: 1.0, top_out: -100.0, board_weights: None };
+        let mut env = Environment::new(EnvConfig { rewards, ..EnvConfig::default() });
+        env.reset(1);
+        env.tetris = Tetris::with_board(&[
+            "XXXXXXXXX.",
+            "XXXXXXXXX.",
+            "XXXXXXXXX.",
+            "XXXXXXXXX.",
+            "XXXXXXXXX.",
+        ], -1, &[I_SHAPE_INDEX]);
+        let observation = env.observe();
+        let index = observation.placements.iter()
+            .position(|placement| placement.get_cells().iter().all(|point| point.x == 9 && point.y >= 18))
+            .unwrap();
+        let (_, reward, done, info) = env.step(Action::Placement(index));
+        let lock = info.lock.unwrap();
+        assert_eq!((lock.rows, lock.attack), (4, 4));
+        assert_eq!(reward, 80.0 + 2.0 * 4.0 + 1.0);
+        assert!(!done);
+    }
+
+    #[test]
+    fn topping_out_is_done() {
+        let rewards = RewardConfig { top_out: -100.0, ..RewardConfig::default() };
+        let mut env = Environment::new(EnvConfig { rewards, ..EnvConfig::default() });
+        env.reset(1);
+        // the shape fills the top two rows, leaving no room for the next one
+        let rows = ["XXXXXXXXX."; ROW_COUNT as usize - 2];
+        env.tetris = Tetris::with_board(&rows, -1, &[SQUARE_SHAPE_INDEX]);
+        env.observe();
+        let (_, reward, done, info) = env.step(Action::Placement(0));
+        assert!(done && !info.truncated);
+        assert_eq!(reward, RewardConfig::default().piece - 100.0);
+        let (_, reward, done, info) = env.step(Action::Placement(0));
+        assert!(done && info.invalid_action);
+        assert_eq!(reward, 0.0);
+    }
+
+    #[test]
+    fn episodes_are_truncated_after_max_pieces() {
+        let mut env = Environment::new(EnvConfig { max_pieces: 2, ..EnvConfig::default() });
+        env.reset(1);
+        assert!(!env.step(Action::Placement(0)).2);
+        let (_, _, done, info) = env.step(Action::Placement(0));
+        assert!(done && info.truncated);
+    }
+}
@@ -0,0 +1,154 @@
+use tetris::*;
+
+/// A FinesseTracker counts finesse faults: shapes moved into place with more keys than the
+/// shortest path to the same placement, see also Tetris::get_placements().
+/// Call press() with each input before it's applied to the game, then lock() with the result
+/// whenever a shape is fixed into place.
+#[derive(Clone, Default)]
+pub struct FinesseTracker {
+    /// The game before the first key was pressed for the current shape, to find the shortest
+    /// paths from
+    start: Option<Tetris>,
+    /// The keys pressed for the current shape so far
+    inputs: Vec<Input>,
+    /// The number of shapes placed with a finesse fault
+    faults: u32,
+    /// The number of shapes placed with keys pressed
+    shapes: u32,
+    /// The shortest path to the last shape placed with a finesse fault
+    last_fault: Option<Placement>,
+}
+
+impl FinesseTracker {
+    pub fn new() -> FinesseTracker {
+        FinesseTracker::default()
+    }
+
+    pub fn get_faults(&self) -> u32 {
+        self.faults
+    }
+
+    pub fn get_shapes(&self) -> u32 {
+        self.shapes
+    }
+
+    /// Returns the shortest path to the last shape placed with a finesse fault
+    pub fn get_last_fault(&self) -> Option<&Placement> {
+        self.last_fault.as_ref()
+    }
+
+    /// Starts counting again, e.g. for a new game
+    pub fn reset(&mut self) {
+        *self = FinesseTracker::default();
+    }
+
+    /// Records a key pressed for the current shape, before it's applied to the game
+    pub fn press(&mut self, tetris: &Tetris, input: Input) {
+        match input {
+            Input::Tick => {},
+            // the shape swapped in by hold is a new shape, with its own paths
+            Input::Hold => {
+                self.start = None;
+                self.inputs.clear();
+            },
+            _ => {
+                if self.start.is_none() {
+                    self.start = Some(tetris.clone());
+                }
+                self.inputs.push(input);
+            },
+        }
+    }
+
+    /// Compares the keys pressed for a shape fixed into place with the shortest path to the same
+    /// cells. Returns true if the shape was placed with a finesse fault.
+    pub fn lock(&mut self, result: &LockResult) -> bool {
+        let start = match self.start.take() {
+            Some(start) => start,
+            None => return false,
+        };
+        // the shape might have been fixed by gravity rather than a hard drop, so only the keys
+        // moving the shape are compared. Left and right repeated by DAS never reach press(), so a
+        // key held to the wall counts once, as it does in the shortest paths.
+        let pressed = count_inputs(&self.inputs) - self.inputs.iter().filter(|input| **input == Input::HardDrop).count();
+        self.inputs.clear();
+        self.shapes += 1;
+
+        let mut cells = result.cells;
+        cells.sort();
+        let shortest = start.get_placements(false).into_iter().find(|placement| {
+            let mut placement_cells = placement.get_cells();
+            placement_cells.sort();
+            placement_cells == cells
+        });
+        match shortest {
+            Some(placement) if pressed > placement.get_input_count() - 1 => {
+                self.faults += 1;
+                self.last_fault = Some(placement);
+                true
+            },
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Presses each input in turn and applies it to the game, returning lock()'s result once the
+    /// shape is fixed into place
+    fn place(tracker: &mut FinesseTracker, tetris: &mut Tetris, inputs: &[Input]) -> bool {
+        for input in inputs {
+            tracker.press(tetris, *input);
+            if let Some(result) = tetris.apply_input(*input) {
+                return tracker.lock(&result);
+            }
+        }
+        panic!("the shape wasn't fixed into place");
+    }
+
+    #[test]
+    fn the_shortest_taps_are_not_a_fault() {
+        let mut tracker = FinesseTracker::new();
+        let mut tetris = Tetris::with_board(&[], -1, &[SQUARE_SHAPE_INDEX, T_SHAPE_INDEX]);
+        assert!(!place(&mut tracker, &mut tetris, &[Input::Left, Input::HardDrop]));
+        assert!(!place(&mut tracker, &mut tetris, &[Input::RotateClockwise, Input::Right, Input::HardDrop]));
+        assert_eq!((tracker.get_faults(), tracker.get_shapes()), (0, 2));
+        assert!(tracker.get_last_fault().is_none());
+    }
+
+    #[test]
+    fn an_extra_rotation_or_tap_is_a_fault() {
+        let mut tracker = FinesseTracker::new();
+        let mut tetris = Tetris::with_board(&[], -1, &[T_SHAPE_INDEX, SQUARE_SHAPE_INDEX]);
+        // turning twice to end up the way the shape started
+        assert!(place(&mut tracker, &mut tetris, &[Input::RotateClockwise, Input::RotateCounterClockwise, Input::HardDrop]));
+        assert_eq!(tracker.get_last_fault().unwrap().path, vec![Input::HardDrop]);
+        // overshooting and coming back
+        assert!(place(&mut tracker, &mut tetris, &[Input::Left, Input::Left, Input::Right, Input::HardDrop]));
+        assert_eq!(tracker.get_last_fault().unwrap().path, vec![Input::Left, Input::HardDrop]);
+        assert_eq!((tracker.get_faults(), tracker.get_shapes()), (2, 2));
+    }
+
+    #[test]
+    fn holding_to_the_wall_is_one_key() {
+        let mut tracker = FinesseTracker::new();
+        let mut tetris = Tetris::with_board(&[], -1, &[SQUARE_SHAPE_INDEX, SQUARE_SHAPE_INDEX]);
+        // the key is pressed once, and DAS repeats it until the shape reaches the wall
+        tracker.press(&tetris, Input::Left);
+        while tetris.set_col(tetris.get_col() - 1) {}
+        assert_eq!(tetris.get_col(), 1);
+        assert!(!place(&mut tracker, &mut tetris, &[Input::HardDrop]));
+        // tapping all the way there takes more keys
+        assert!(place(&mut tracker, &mut tetris, &[Input::Left, Input::Left, Input::Left, Input::Left, Input::HardDrop]));
+    }
+
+    #[test]
+    fn keys_before_hold_are_not_counted() {
+        let mut tracker = FinesseTracker::new();
+        let mut tetris = Tetris::with_board(&[], -1, &[T_SHAPE_INDEX, SQUARE_SHAPE_INDEX]);
+        assert!(!place(&mut tracker, &mut tetris, &[Input::Right, Input::Right, Input::Hold, Input::HardDrop]));
+        assert_eq!(tracker.get_shapes(), 1);
+    }
+}
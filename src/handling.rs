@@ -0,0 +1,215 @@
+use tetris::*;
+
+/// How keys held down repeat, in milliseconds like most games show them
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+pub struct Handling {
+    /// Delayed Auto Shift, the time left or right is held before the shape starts moving on its own
+    pub das: f64,
+    /// Auto Repeat Rate, the time between each move once the shape moves on its own.
+    /// 0 moves the shape all the way to the wall at once.
+    pub arr: f64,
+    /// How many times faster than gravity the shape falls while soft drop is held
+    pub soft_drop_factor: f64,
+}
+
+impl Default for Handling {
+    fn default() -> Handling {
+        Handling {
+            das: 167.0,
+            arr: 33.0,
+            soft_drop_factor: 20.0,
+        }
+    }
+}
+
+/// Left or right held down, see also AutoRepeat
+#[derive(Copy, Clone, Debug)]
+struct Shift {
+    input: Input,
+    /// The time since the key was pressed, or the shape last moved once DAS has passed, in milliseconds
+    elapsed: f64,
+    /// True once the key was held for longer than DAS
+    charged: bool,
+}
+
+/// An AutoRepeat follows the keys a player holds down, repeating left, right and soft drop at the
+/// rates of a Handling. Call press() and release() as keys go down and up, and update() every frame
+/// to get the inputs repeated since the last frame.
+#[derive(Clone, Debug, Default)]
+pub struct AutoRepeat {
+    handling: Handling,
+    /// The inputs whose keys are held down, so the repeated presses sent by the operating
+    /// system are ignored
+    held: Vec<Input>,
+    /// The direction pressed most recently, while it's held
+    shift: Option<Shift>,
+    /// The time since the shape last fell by soft drop while it's held, in milliseconds
+    soft_drop: Option<f64>,
+}
+
+impl AutoRepeat {
+    pub fn new(handling: Handling) -> AutoRepeat {
+        AutoRepeat {
+            handling,
+            ..AutoRepeat::default()
+        }
+    }
+
+    pub fn get_handling(&self) -> Handling {
+        self.handling
+    }
+
+    /// Records a key pressed for an input. Returns true if the key wasn't already held, in which
+    /// case the input should be applied once right away.
+    pub fn press(&mut self, input: Input) -> bool {
+        if self.held.contains(&input) {
+            return false;
+        }
+        self.held.push(input);
+        match input {
+            Input::Left | Input::Right => self.shift = Some(Shift { input, elapsed: 0.0, charged: false }),
+            Input::SoftDrop => self.soft_drop = Some(0.0),
+            _ => {},
+        }
+        true
+    }
+
+    /// Records a key released for an input
+    pub fn release(&mut self, input: Input) {
+        self.held.retain(|held| *held != input);
+        match input {
+            // the other direction takes over if it's still held, starting its DAS again
+            Input::Left | Input::Right if self.shift.is_some_and(|shift| shift.input == input) => {
+                self.shift = self.held.iter()
+                    .find(|held| **held == Input::Left || **held == Input::Right)
+                    .map(|held| Shift { input: *held, elapsed: 0.0, charged: false });
+            },
+            Input::SoftDrop => self.soft_drop = None,
+            _ => {},
+        }
+    }
+
+    /// Releases every key, e.g. when the window loses focus and the releases won't arrive
+    pub fn release_all(&mut self) {
+        *self = AutoRepeat::new(self.handling);
+    }
+
+    /// Advances the held keys by dt seconds, returning the inputs repeated in that time.
+    /// tick_time is the time between ticks of the game in seconds, see Tetris::get_tick_time().
+    pub fn update(&mut self, dt: f64, tick_time: f64) -> Vec<Input> {
+        let mut result = Vec::new();
+        let dt = dt * 1000.0;
+        let handling = self.handling;
+
+        if let Some(ref mut shift) = self.shift {
+            shift.elapsed += dt;
+            if !shift.charged && shift.elapsed >= handling.das {
+                shift.charged = true;
+                shift.elapsed -= handling.das;
+                result.push(shift.input);
+            }
+            if shift.charged {
+                if handling.arr <= 0.0 {
+                    // moves past the wall are ignored by the game
+                    result.extend((0..COL_COUNT).map(|_| shift.input));
+                    shift.elapsed = 0.0;
+                } else {
+                    while shift.elapsed >= handling.arr && result.len() < COL_COUNT as usize {
+                        shift.elapsed -= handling.arr;
+                        result.push(shift.input);
+                    }
+                    shift.elapsed = shift.elapsed.min(handling.arr);
+                }
+            }
+        }
+
+        if let Some(ref mut elapsed) = self.soft_drop {
+            let interval = tick_time * 1000.0 / handling.soft_drop_factor.max(1.0);
+            *elapsed += dt;
+            let mut rows = 0;
+            while *elapsed >= interval && rows < ROW_COUNT {
+                *elapsed -= interval;
+                rows += 1;
+                result.push(Input::SoftDrop);
+            }
+            *elapsed = elapsed.min(interval);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auto_repeat(das: f64, arr: f64) -> AutoRepeat {
+        AutoRepeat::new(Handling { das, arr, soft_drop_factor: 20.0 })
+    }
+
+    #[test]
+    fn shifting_starts_after_das_then_repeats_at_arr() {
+        let mut auto_repeat = auto_repeat(125.0, 20.0);
+        assert!(auto_repeat.press(Input::Left));
+        // the key repeated by the operating system is ignored
+        assert!(!auto_repeat.press(Input::Left));
+        assert!(auto_repeat.update(0.120, 1.0).is_empty());
+        assert_eq!(auto_repeat.update(0.010, 1.0), vec![Input::Left]);
+        assert_eq!(auto_repeat.update(0.020, 1.0), vec![Input::Left]);
+        assert_eq!(auto_repeat.update(0.040, 1.0), vec![Input::Left, Input::Left]);
+        auto_repeat.release(Input::Left);
+        assert!(auto_repeat.update(0.5, 1.0).is_empty());
+    }
+
+    #[test]
+    fn zero_arr_moves_to_the_wall() {
+        let mut auto_repeat = auto_repeat(100.0, 0.0);
+        let mut tetris = Tetris::with_board(&[], -1, &[SQUARE_SHAPE_INDEX]);
+        auto_repeat.press(Input::Right);
+        tetris.apply_input(Input::Right);
+        assert!(auto_repeat.update(0.050, 1.0).is_empty());
+        for input in auto_repeat.update(0.060, 1.0) {
+            tetris.apply_input(input);
+        }
+        assert_eq!(tetris.get_col(), COL_COUNT as i32 - 1);
+    }
+
+    #[test]
+    fn soft_drop_falls_faster_than_gravity_by_the_factor() {
+        let mut auto_repeat = auto_repeat(125.0, 20.0);
+        auto_repeat.press(Input::SoftDrop);
+        // a tick every second, so every 50 milliseconds with a factor of 20
+        assert_eq!(auto_repeat.update(0.120, 1.0), vec![Input::SoftDrop, Input::SoftDrop]);
+        assert_eq!(auto_repeat.update(0.040, 1.0), vec![Input::SoftDrop]);
+        auto_repeat.release(Input::SoftDrop);
+        assert!(auto_repeat.update(1.0, 1.0).is_empty());
+    }
+
+    #[test]
+    fn the_last_direction_pressed_wins() {
+        let mut auto_repeat = auto_repeat(125.0, 20.0);
+        auto_repeat.press(Input::Left);
+        assert_eq!(auto_repeat.update(0.130, 1.0), vec![Input::Left]);
+        // pressing the other way starts its own DAS
+        auto_repeat.press(Input::Right);
+        assert!(auto_repeat.update(0.120, 1.0).is_empty());
+        assert_eq!(auto_repeat.update(0.010, 1.0), vec![Input::Right]);
+        // releasing it hands back to the direction still held, which waits for DAS again
+        auto_repeat.release(Input::Right);
+        assert!(auto_repeat.update(0.120, 1.0).is_empty());
+        assert_eq!(auto_repeat.update(0.010, 1.0), vec![Input::Left]);
+        // releasing the direction that isn't moving the shape changes nothing
+        auto_repeat.press(Input::Right);
+        auto_repeat.release(Input::Left);
+        assert_eq!(auto_repeat.update(0.130, 1.0), vec![Input::Right]);
+    }
+
+    #[test]
+    fn release_all_stops_every_key() {
+        let mut auto_repeat = auto_repeat(125.0, 20.0);
+        auto_repeat.press(Input::Left);
+        auto_repeat.press(Input::SoftDrop);
+        auto_repeat.release_all();
+        assert!(auto_repeat.update(1.0, 1.0).is_empty());
+        assert!(auto_repeat.press(Input::Left));
+    }
+}
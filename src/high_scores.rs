@@ -0,0 +1,209 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json;
+
+/// The number of scores kept in each table
+pub const MAX_HIGH_SCORES: usize = 10;
+
+/// A game that made it into a high-score table
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HighScore {
+    pub name: String,
+    pub score: u32,
+    /// The number of rows completed
+    pub lines: u32,
+    /// The level reached
+    pub level: u32,
+    /// The time the game was played for, in seconds, not counting pauses
+    pub time: f64,
+    /// When the game was played, in seconds since 1970-01-01 UTC
+    pub date: u64,
+}
+
+/// HighScores keeps a table of the best scores for each mode and ruleset, e.g. marathon games
+/// with the guideline attack table, saved to a JSON file. Scores from different rules are never
+/// compared, since they aren't played the same way.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct HighScores {
+    /// The tables by their key, see table_key(), each sorted from the highest score down
+    tables: BTreeMap<String, Vec<HighScore>>,
+    /// The name entered last, to suggest for the next high score
+    last_name: String,
+}
+
+impl HighScores {
+    /// Loads the high scores from a file, or returns empty tables if the file doesn't exist yet
+    pub fn load(path: &Path) -> io::Result<HighScores> {
+        match fs::read_to_string(path) {
+            Ok(text) => Ok(serde_json::from_str(&text)?),
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(HighScores::default()),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Saves the high scores, creating the directory of the file if needed
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+
+    /// Returns the file high scores are saved to in the user's data directory, e.g.
+    /// ~/.local/share/tetris-piston/high_scores.json on Linux, or None if there's no data directory
+    pub fn default_path() -> Option<PathBuf> {
+        let home = || env::var_os("HOME").map(PathBuf::from);
+        let data_dir = if cfg!(windows) {
+            env::var_os("APPDATA").map(PathBuf::from)
+        } else if cfg!(target_os = "macos") {
+            home().map(|home| home.join("Library").join("Application Support"))
+        } else {
+            env::var_os("XDG_DATA_HOME").map(PathBuf::from)
+                .filter(|dir| dir.is_absolute())
+                .or_else(|| home().map(|home| home.join(".local").join("share")))
+        };
+        data_dir.map(|dir| dir.join("tetris-piston").join("high_scores.json"))
+    }
+
+    /// Returns the key of the table for a mode and ruleset
+    pub fn table_key(mode: &str, ruleset: &str) -> String {
+        format!("{}/{}", mode, ruleset)
+    }
+
+    /// Returns the scores of a mode and ruleset, from the highest score down
+    pub fn get_table(&self, mode: &str, ruleset: &str) -> &[HighScore] {
+        self.tables.get(&HighScores::table_key(mode, ruleset)).map_or(&[], |table| table.as_slice())
+    }
+
+    /// Returns true if a score would make it into the table of a mode and ruleset
+    pub fn qualifies(&self, mode: &str, ruleset: &str, score: u32) -> bool {
+        let table = self.get_table(mode, ruleset);
+        score > 0 && (table.len() < MAX_HIGH_SCORES || table.iter().any(|high_score| score > high_score.score))
+    }
+
+    /// Adds a score to the table of a mode and ruleset, below any equal scores. Returns its rank
+    /// from 0, or None if it didn't make it into the table.
+    pub fn add(&mut self, mode: &str, ruleset: &str, high_score: HighScore) -> Option<usize> {
+        if !self.qualifies(mode, ruleset, high_score.score) {
+            return None;
+        }
+        self.last_name = high_score.name.clone();
+        let table = self.tables.entry(HighScores::table_key(mode, ruleset)).or_default();
+        let rank = table.iter().position(|other| high_score.score > other.score).unwrap_or(table.len());
+        table.insert(rank, high_score);
+        table.truncate(MAX_HIGH_SCORES);
+        Some(rank)
+    }
+
+    pub fn get_last_name(&self) -> &str {
+        &self.last_name
+    }
+}
+
+/// Returns the current time in seconds since 1970-01-01 UTC, see also HighScore::date
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs())
+}
+
+/// Formats a time in seconds since 1970-01-01 UTC as a date, e.g. 2024-03-09
+pub fn format_date(date: u64) -> String {
+    // converts days to a year, month and day of the proleptic Gregorian calendar, counting in
+    // eras of 400 years that start on March 1st so the leap day comes last
+    let days = (date / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Formats a time in seconds as minutes and seconds, e.g. 3:05
+pub fn format_time(time: f64) -> String {
+    let seconds = time.max(0.0) as u64;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn high_score(name: &str, score: u32) -> HighScore {
+        HighScore { name: name.to_string(), score, lines: 0, level: 0, time: 0.0, date: 0 }
+    }
+
+    #[test]
+    fn scores_qualify_until_the_table_is_full() {
+        let mut high_scores = HighScores::default();
+        assert!(!high_scores.qualifies("marathon", "guideline", 0));
+        for score in 1..=MAX_HIGH_SCORES as u32 {
+            assert!(high_scores.qualifies("marathon", "guideline", score * 100));
+            high_scores.add("marathon", "guideline", high_score("a", score * 100));
+        }
+        // the table is full, so only scores above its lowest make it in
+        assert!(!high_scores.qualifies("marathon", "guideline", 100));
+        assert!(high_scores.qualifies("marathon", "guideline", 101));
+    }
+
+    #[test]
+    fn scores_are_sorted_and_capped() {
+        let mut high_scores = HighScores::default();
+        assert_eq!(high_scores.add("marathon", "guideline", high_score("a", 500)), Some(0));
+        assert_eq!(high_scores.add("marathon", "guideline", high_score("b", 900)), Some(0));
+        // equal scores go below the ones already in the table
+        assert_eq!(high_scores.add("marathon", "guideline", high_score("c", 500)), Some(2));
+        assert_eq!(high_scores.add("marathon", "guideline", high_score("d", 700)), Some(1));
+        let names: Vec<&str> = high_scores.get_table("marathon", "guideline").iter().map(|high_score| high_score.name.as_str()).collect();
+        assert_eq!(names, vec!["b", "d", "a", "c"]);
+        assert_eq!(high_scores.get_last_name(), "d");
+
+        for score in 1..=MAX_HIGH_SCORES as u32 {
+            high_scores.add("marathon", "guideline", high_score("e", 1000 + score));
+        }
+        let table = high_scores.get_table("marathon", "guideline");
+        assert_eq!(table.len(), MAX_HIGH_SCORES);
+        assert_eq!((table[0].score, table[MAX_HIGH_SCORES - 1].score), (1010, 1001));
+        assert_eq!(high_scores.add("marathon", "guideline", high_score("f", 1000)), None);
+        assert_eq!(high_scores.get_last_name(), "e");
+    }
+
+    #[test]
+    fn modes_and_rulesets_have_separate_tables() {
+        let mut high_scores = HighScores::default();
+        high_scores.add("marathon", "guideline", high_score("a", 100));
+        high_scores.add("marathon", "tetrio", high_score("b", 200));
+        high_scores.add("sprint", "guideline", high_score("c", 300));
+        assert_eq!(high_scores.get_table("marathon", "guideline")[0].name, "a");
+        assert_eq!(high_scores.get_table("marathon", "tetrio")[0].name, "b");
+        assert_eq!(high_scores.get_table("sprint", "guideline")[0].name, "c");
+        assert!(high_scores.get_table("sprint", "tetrio").is_empty());
+        assert_eq!(high_scores.get_table("marathon", "guideline").len(), 1);
+    }
+
+    #[test]
+    fn dates_are_formatted() {
+        assert_eq!(format_date(0), "1970-01-01");
+        assert_eq!(format_date(86399), "1970-01-01");
+        // leap days, including one in a year divisible by 400
+        assert_eq!(format_date(1709164800), "2024-02-29");
+        assert_eq!(format_date(1709251200), "2024-03-01");
+        assert_eq!(format_date(951782400), "2000-02-29");
+        assert_eq!(format_date(1677628800 - 1), "2023-02-28");
+        assert_eq!(format_date(1735603200), "2024-12-31");
+    }
+
+    #[test]
+    fn times_are_formatted() {
+        assert_eq!(format_time(0.0), "0:00");
+        assert_eq!(format_time(185.9), "3:05");
+        assert_eq!(format_time(-1.0), "0:00");
+    }
+}
@@ -0,0 +1,25 @@
+//! The game itself, apart from the window. tetris.rs is the game, and the other modules build on it:
+//! bots that play the game, a perfect clear solver, openers, finesse, network versus games, headless simulations and an environment for
+//! training agents. handling.rs repeats the keys held down by players, and controls.rs
+//! binds keys to the actions players take. high_scores.rs keeps the best games of each mode.
+//! main.rs adds the window, rendering and keyboard events, see also src/bin for the other binaries.
+
+extern crate rand;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+
+pub mod tetris;
+pub mod ai;
+pub mod tbp;
+pub mod net;
+pub mod rollback;
+pub mod simulation;
+pub mod env;
+pub mod perfect_clear;
+pub mod openers;
+pub mod finesse;
+pub mod handling;
+pub mod controls;
+pub mod high_scores;
@@ -1,23 +1,328 @@
 extern crate piston_window;
+#[cfg(feature = "gamepad")]
+extern crate gilrs;
 extern crate graphics;
 extern crate rand;
-
-pub mod tetris;
+extern crate serde_json;
+#[macro_use]
+extern crate serde_derive;
+extern crate tetris_piston;
 
 use piston_window::*;
+use rand::Rng;
 
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::fs::OpenOptions;
-use tetris::*;
+use std::sync::mpsc;
+use std::thread;
+use tetris_piston::tetris::*;
+use tetris_piston::tetris::Input;
+use tetris_piston::net::{Connection, Message};
+use tetris_piston::rollback::{FrameInput, RollbackSession, VersusState, FRAMES_PER_SECOND};
+use tetris_piston::ai::{self, AiPlayer, BeamSearch, Planner, Weights};
+use tetris_piston::finesse::FinesseTracker;
+use tetris_piston::handling::{AutoRepeat, Handling};
+use tetris_piston::controls::{Action, Bindings, Direction, GamepadBindings, GamepadState};
+use tetris_piston::high_scores::{self, HighScore, HighScores};
+use tetris_piston::openers::{self, OpenerProgress};
+use tetris_piston::perfect_clear::PerfectClearSolver;
+use tetris_piston::tbp::TbpBot;
 
-struct App {
+/// The keys and gamepad buttons bound to each action, loaded from a JSON file at startup and saved
+/// by the rebinding screen. In a versus game each player has their own keys.
+#[derive(Serialize, Clone)]
+struct KeyConfig {
+    /// The keys for the actions that apply to every player, like starting a new game
+    game: Bindings<Key>,
+    single_player: Bindings<Key>,
+    player_one: Bindings<Key>,
+    player_two: Bindings<Key>,
+    /// The buttons, D-pad and sticks of every gamepad
+    gamepad: GamepadBindings,
+}
+
+impl KeyConfig {
+    /// Loads the keys from a file, or returns the default keys if the file doesn't exist yet.
+    /// The actions the file leaves out keep their default keys.
+    fn load(path: &Path) -> io::Result<KeyConfig> {
+        let mut result = KeyConfig::default();
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => return Ok(result),
+            Err(error) => return Err(error),
+        };
+        let sections: BTreeMap<String, serde_json::Value> = serde_json::from_str(&text)?;
+        for (name, value) in sections.into_iter() {
+            let section = match name.as_str() {
+                "game" => &mut result.game,
+                "single_player" => &mut result.single_player,
+                "player_one" => &mut result.player_one,
+                "player_two" => &mut result.player_two,
+                "gamepad" => {
+                    // the axes and dead zone replace the defaults, if the file has them
+                    let gamepad: GamepadBindings = serde_json::from_value(value)?;
+                    result.gamepad.buttons.merge(&gamepad.buttons);
+                    result.gamepad.d_pad.merge(&gamepad.d_pad);
+                    result.gamepad.axes = gamepad.axes;
+                    result.gamepad.dead_zone = gamepad.dead_zone;
+                    continue;
+                },
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unknown section '{}'", name))),
+            };
+            section.merge(&serde_json::from_value(value)?);
+        }
+        match result.conflicts().first() {
+            Some(conflict) => Err(io::Error::new(io::ErrorKind::InvalidData, conflict.clone())),
+            None => Ok(result),
+        }
+    }
+
+    /// Returns a description of each key bound twice where both would be used in the same game: as
+    /// a game key and a player key, or for both players of a versus game. Player keys are checked
+    /// after the game keys, so their presses would be lost.
+    fn conflicts(&self) -> Vec<String> {
+        let pairs = [("game", &self.game, "single_player", &self.single_player),
+            ("game", &self.game, "player_one", &self.player_one),
+            ("game", &self.game, "player_two", &self.player_two),
+            ("player_one", &self.player_one, "player_two", &self.player_two)];
+        pairs.iter()
+            .flat_map(|(name, bindings, other_name, other)| bindings.shared_keys(other).into_iter()
+                .map(move |key| format!("{:?} is bound to both {} in {} and {} in {}", key,
+                    bindings.action(key).unwrap().get_name(), name, other.action(key).unwrap().get_name(), other_name)))
+            .collect()
+    }
+
+    fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+
+    /// Returns bindings for the player actions, from a key for each action in the order of Action::PLAYER_ACTIONS
+    fn player_bindings(keys: [Key; 8]) -> Bindings<Key> {
+        let mut result = Bindings::new();
+        for (action, key) in Action::PLAYER_ACTIONS.iter().zip(keys.iter()) {
+            result.bind(*action, &[*key]);
+        }
+        result
+    }
+}
+
+impl Default for KeyConfig {
+    fn default() -> KeyConfig {
+        let mut game = Bindings::new();
+        for (action, key) in [(Action::NewGame, Key::N), (Action::Pause, Key::P), (Action::LevelDown, Key::K), (Action::LevelUp, Key::L),
+            (Action::ToggleAi, Key::I), (Action::PerfectClears, Key::F), (Action::Openers, Key::O),
+            (Action::FinesseFlash, Key::G), (Action::Hints, Key::H), (Action::Rebind, Key::B), (Action::HighScores, Key::T)] {
+            game.bind(action, &[key]);
+        }
+        KeyConfig {
+            game,
+            single_player: KeyConfig::player_bindings([Key::Left, Key::Right, Key::Down, Key::Space, Key::Up, Key::Z, Key::A, Key::C]),
+            player_one: KeyConfig::player_bindings([Key::A, Key::D, Key::S, Key::Space, Key::W, Key::E, Key::R, Key::Q]),
+            player_two: KeyConfig::player_bindings([Key::Left, Key::Right, Key::Down, Key::Return, Key::Up, Key::RCtrl,
+                Key::Slash, Key::RShift]),
+            gamepad: GamepadBindings::default(),
+        }
+    }
+}
+
+/// Returns the names of the keys bound to an action to show in help text, e.g. 'N' or Space
+fn get_key_names(bindings: &Bindings<Key>, action: Action) -> String {
+    let names: Vec<String> = bindings.get_keys(action).iter()
+        .map(|key| {
+            let name = format!("{:?}", key);
+            if name.len() == 1 { format!("'{}'", name) } else { name }
+        })
+        .collect();
+    if names.is_empty() { String::from("(unbound)") } else { names.join("/") }
+}
+
+/// The rebinding screen, which asks for the keys of each player action in turn
+struct Rebinding {
+    /// The index of the player whose keys are being bound
+    player: usize,
+    /// The index in Action::PLAYER_ACTIONS of the action being bound
+    action: usize,
+    /// The keys pressed for the action so far
+    keys: Vec<Key>,
+    /// Why the last key pressed wasn't bound, if it was already taken
+    rejected: Option<String>,
+}
+
+/// The prompt for the name of a player whose game made the high-score table
+struct NameEntry {
+    /// The index of the player
+    player: usize,
+    /// The game, with the name entered so far
+    high_score: HighScore,
+}
+
+/// The most characters in a name entered for a high score
+const MAX_NAME_LENGTH: usize = 12;
+
+/// Each player has their own game, timer, keys and area of the window
+struct Player {
     tetris: Tetris,
     elapsed_time: f64,
+    /// The player's keys, or None if the player is an opponent playing over the network
+    keys: Option<Bindings<Key>>,
+    /// Repeats the player's keys while they're held down
+    auto_repeat: AutoRepeat,
+    /// The x offset of the player's game board and status column within the window
+    left_margin: f64,
+    /// Plays the game in place of the player's keys while the AI is switched on
+    ai: Option<AiPlayer>,
+    /// The time elapsed since the AI last pressed a key
+    ai_elapsed_time: f64,
+    /// The placements of a perfect clear found for the game, shown while the overlay is switched on
+    perfect_clear: Option<Vec<Placement>>,
+    /// The perfect clear search running on another thread for the current shape, see also spawn_search()
+    perfect_clear_search: Option<mpsc::Receiver<Option<Vec<Placement>>>>,
+    /// The opener the player is building, and the placement that builds its next part,
+    /// shown while the opener trainer is switched on
+    opener: Option<(OpenerProgress, Option<Placement>)>,
+    /// The placement recommended for the current shape and the reasons why, shown while the
+    /// hint is switched on
+    hint: Option<(Placement, Vec<&'static str>)>,
+    /// The hint search running on another thread for the current shape
+    hint_search: Option<mpsc::Receiver<Option<(Placement, Vec<&'static str>)>>>,
+    /// Counts the shapes the player moved into place with more keys than needed
+    finesse: FinesseTracker,
+    /// The time left to show the shortest path after a finesse fault
+    finesse_flash_time: f64,
+    /// The shape count and held shape when the overlays were last updated, so they're updated
+    /// again once either changes
+    overlay_state: Option<(u32, i32)>,
+    /// The time the current game has been played for, not counting pauses
+    game_time: f64,
+    /// True if the AI played any part of the current game, which keeps it out of the high scores
+    ai_played: bool,
+    /// True once the score of the last game was checked against the high scores
+    score_recorded: bool,
+}
+
+impl Player {
+    fn new(keys: Option<Bindings<Key>>, left_margin: f64, attack_table: &AttackTable, handling: Handling) -> Player {
+        let mut tetris = Tetris::new();
+        tetris.set_attack_table(attack_table.clone());
+        Player {
+            tetris,
+            elapsed_time: 0.0,
+            keys,
+            auto_repeat: AutoRepeat::new(handling),
+            left_margin,
+            ai: None,
+            ai_elapsed_time: 0.0,
+            perfect_clear: None,
+            perfect_clear_search: None,
+            opener: None,
+            hint: None,
+            hint_search: None,
+            finesse: FinesseTracker::new(),
+            finesse_flash_time: 0.0,
+            overlay_state: None,
+            game_time: 0.0,
+            ai_played: false,
+            score_recorded: true,
+        }
+    }
+
+    /// Returns true if the player is an opponent playing over the network, whose game
+    /// is replayed from the inputs they send
+    fn is_remote(&self) -> bool {
+        self.keys.is_none()
+    }
+}
+
+/// A versus game against an opponent over the network. Both players simulate both games
+/// one frame at a time, see also RollbackSession.
+struct NetworkGame {
+    connection: Connection,
+    session: RollbackSession,
+    /// The local player's inputs pressed since the last frame
+    input: FrameInput,
+    /// The time elapsed since the last frame was simulated
+    elapsed_time: f64,
+}
+
+impl NetworkGame {
+    /// Starts a network game after connecting to the opponent. The host passes the starting level,
+    /// decides the seed, and is always the first player of the VersusState. The player joining
+    /// passes None, and plays from the starting level the host sends.
+    fn new(mut connection: Connection, host_level: Option<u32>, attack_table: &AttackTable) -> io::Result<NetworkGame> {
+        let (seed, starting_level) = match host_level {
+            Some(starting_level) => {
+                let seed: u64 = rand::random();
+                connection.send(&Message::Start { seed, starting_level });
+                (seed, starting_level)
+            },
+            None => match connection.wait_receive() {
+                Some(Message::Start { seed, starting_level }) => (seed, starting_level),
+                Some(message) => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                    format!("Expected the host to start the game, received {:?}", message))),
+                None => return Err(io::Error::new(io::ErrorKind::ConnectionAborted,
+                    "The host disconnected before starting the game")),
+            },
+        };
+        let local_index = if host_level.is_some() { 0 } else { 1 };
+        Ok(NetworkGame {
+            connection,
+            session: RollbackSession::new(local_index, VersusState::new(seed, starting_level, attack_table)),
+            input: FrameInput::default(),
+            elapsed_time: 0.0,
+        })
+    }
+}
+
+struct App {
+    players: Vec<Player>,
+    /// In a versus game, the index of the last player left standing
+    winner: Option<usize>,
+    /// The game against an opponent over the network, if any. The first player is the local
+    /// player, and the second player is the opponent.
+    network: Option<NetworkGame>,
+    /// The command that starts an external bot to play in place of the built-in AI, see also TbpBot
+    bot_command: Option<Vec<String>>,
+    /// The weights of the built-in AI
+    weights: Weights,
+    /// Searches for perfect clears to show as a training overlay, or None while the overlay is switched off
+    perfect_clear_solver: Option<PerfectClearSolver>,
+    /// True while the opener trainer shows the opener being built
+    opener_trainer: bool,
+    /// Recommends placements to show as a hint, or None while the hint is switched off
+    hint_planner: Option<BeamSearch>,
+    /// The keys of every player, see also Player::keys
+    key_config: KeyConfig,
+    /// The file the keys are saved to after rebinding them
+    key_config_path: PathBuf,
+    /// The rebinding screen, while it's shown
+    rebinding: Option<Rebinding>,
+    /// The sticks and D-pad of each gamepad by its id, see also gamepad_player()
+    gamepads: Vec<(u32, GamepadState)>,
+    /// True to show the shortest path for a while after each finesse fault
+    flash_finesse: bool,
+    /// The best games of each mode and ruleset
+    high_scores: HighScores,
+    /// The file the high scores are saved to, or None if they can't be saved
+    high_scores_path: Option<PathBuf>,
+    /// The rules the games are played by, the name of the attack table, see also HighScores
+    ruleset: String,
+    /// The players waiting to enter their name for a high score, the first is asked first
+    name_entries: Vec<NameEntry>,
+    /// True while the high-score table is shown in place of the games
+    show_high_scores: bool,
+    /// True when showing the high-score table paused the games, so closing it resumes them
+    high_scores_paused: bool,
+    /// The rank of the last high score entered, shown highlighted in the table
+    high_score_rank: Option<usize>,
     glyphs: piston_window::Glyphs
 }
 
 //const WHITE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
 const BLACK: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+const GRAY: [f32; 4] = [0.5, 0.5, 0.5, 1.0];
 const LIGHT_GRAY: [f32; 4] = [0.3, 0.3, 0.3, 1.0];
 const DARK_GRAY: [f32; 4] = [0.1, 0.1, 0.1, 1.0];
 const GREEN: [f32; 4] = [0.0, 1.0, 0.0, 1.0];
@@ -37,6 +342,7 @@ fn get_shape_color(shape_index: i32) -> [f32; 4] {
         4 => ORANGE,
         5 => BLUE,
         6 => CYAN,
+        GARBAGE_SHAPE_INDEX => GRAY,
         _ => {
             BLACK
         }
@@ -48,11 +354,96 @@ const CELL_SIZE: f64 = 30.0;
 const LEFT_MARGIN: f64 = 20f64;
 const TOP_MARGIN: f64 = 30f64;
 
+const GARBAGE_METER_WIDTH: f64 = 8f64;
+
 const STATUS_LEFT_MARGIN: f64 = 400f64;
 const STATUS_TOP_MARGIN: f64 = 100f64;
 const LINE_HEIGHT: f64 = 40f64;
 const STATUS_PREVIEW_GRID_HEIGHT: f64 = CELL_SIZE * 6f64;
 
+/// The width of the window area given to each player in a versus game
+const PANEL_WIDTH: f64 = 800f64;
+const WINDOW_WIDTH: f64 = 1024f64;
+const WINDOW_HEIGHT: f64 = 768f64;
+
+/// The time between each key the AI presses, so its moves can be followed
+const AI_INPUT_TIME: f64 = 0.05;
+
+/// The time the shortest path is shown after a finesse fault
+const FINESSE_FLASH_TIME: f64 = 2.0;
+
+/// Returns the number of a gamepad button, in the order of an Xbox style gamepad
+#[cfg(feature = "gamepad")]
+fn gamepad_button(button: gilrs::Button) -> Option<u8> {
+    match button {
+        gilrs::Button::South => Some(0),
+        gilrs::Button::East => Some(1),
+        gilrs::Button::West => Some(2),
+        gilrs::Button::North => Some(3),
+        gilrs::Button::LeftTrigger => Some(4),
+        gilrs::Button::RightTrigger => Some(5),
+        gilrs::Button::Select => Some(6),
+        gilrs::Button::Start => Some(7),
+        gilrs::Button::LeftThumb => Some(8),
+        gilrs::Button::RightThumb => Some(9),
+        gilrs::Button::Mode => Some(10),
+        gilrs::Button::LeftTrigger2 => Some(11),
+        gilrs::Button::RightTrigger2 => Some(12),
+        _ => None,
+    }
+}
+
+/// Returns the direction of a D-pad button
+#[cfg(feature = "gamepad")]
+fn gamepad_direction(button: gilrs::Button) -> Option<Direction> {
+    match button {
+        gilrs::Button::DPadUp => Some(Direction::Up),
+        gilrs::Button::DPadDown => Some(Direction::Down),
+        gilrs::Button::DPadLeft => Some(Direction::Left),
+        gilrs::Button::DPadRight => Some(Direction::Right),
+        _ => None,
+    }
+}
+
+/// Returns the number of a stick or trigger axis, in the order of an Xbox style gamepad
+#[cfg(feature = "gamepad")]
+fn gamepad_axis(axis: gilrs::Axis) -> Option<u8> {
+    match axis {
+        gilrs::Axis::LeftStickX => Some(0),
+        gilrs::Axis::LeftStickY => Some(1),
+        gilrs::Axis::RightStickX => Some(2),
+        gilrs::Axis::RightStickY => Some(3),
+        gilrs::Axis::LeftZ => Some(4),
+        gilrs::Axis::RightZ => Some(5),
+        _ => None,
+    }
+}
+
+/// Runs a search on another thread, so the game keeps running while it searches. Returns the
+/// receiver of its result, to poll each frame. Dropping the receiver ignores the result.
+fn spawn_search<T: Send + 'static, F: FnOnce() -> T + Send + 'static>(search: F) -> mpsc::Receiver<T> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = sender.send(search());
+    });
+    receiver
+}
+
+/// Returns a short name for a key in a path, to show the shortest path after a finesse fault
+fn get_input_name(input: Input) -> &'static str {
+    match input {
+        Input::Left => "Left",
+        Input::Right => "Right",
+        Input::RotateClockwise => "CW",
+        Input::RotateCounterClockwise => "CCW",
+        Input::Rotate180 => "180",
+        Input::SoftDrop => "Down",
+        Input::HardDrop => "Drop",
+        Input::Hold => "Hold",
+        Input::Tick => "",
+    }
+}
+
 struct Render;
 
 impl Render {
@@ -73,13 +464,44 @@ impl Render {
         for point in tetris.get_next_shape().iter() {
             let color = get_shape_color(tetris.get_next_shape_index());
             // render the shape at col 2 and row 2
-            let (x, y) = ((2 as i16 + point.x) as f64 * CELL_SIZE, 
-                (2 as i16 + point.y) as f64 * CELL_SIZE);
-            Render::render_cell(&c, gl, transform.trans(x, y).transform, color);
+            let (x, y) = ((2i16 + point.x) as f64 * CELL_SIZE, 
+                (2i16 + point.y) as f64 * CELL_SIZE);
+            Render::render_cell(c, gl, transform.trans(x, y).transform, color);
+        }
+        // ...and the shapes after it at half size, in a column to the right of the held shape
+        let queue_transform = transform.trans(11f64 * CELL_SIZE, 0f64).scale(0.5, 0.5);
+        for (index, shape_index) in tetris.get_next_queue().iter().enumerate().skip(1) {
+            let color = get_shape_color(*shape_index);
+            for point in SHAPES[*shape_index as usize].iter() {
+                // render each shape at col 1, 3 rows apart
+                let (x, y) = ((1i16 + point.x) as f64 * CELL_SIZE,
+                    ((index as i16 - 1) * 3 + 1 + point.y) as f64 * CELL_SIZE);
+                Render::render_cell(c, gl, queue_transform.trans(x, y).transform, color);
+            }
         }
         transform.trans(0f64, STATUS_PREVIEW_GRID_HEIGHT)
     }
 
+    pub fn render_hold_shape(c: &graphics::Context, 
+        gl: &mut piston_window::G2d, 
+        tetris: &Tetris,
+        transform: graphics::context::Context) {
+        // render the held shape next to the preview, dimmed if it can't be held again yet
+        let shape_index = tetris.get_hold_shape_index();
+        if shape_index >= 0 {
+            let mut color = get_shape_color(shape_index);
+            if !tetris.get_can_hold() {
+                color[3] = 0.4;
+            }
+            for point in SHAPES[shape_index as usize].iter() {
+                // render the shape at col 7 and row 2
+                let (x, y) = ((7i16 + point.x) as f64 * CELL_SIZE, 
+                    (2i16 + point.y) as f64 * CELL_SIZE);
+                Render::render_cell(c, gl, transform.trans(x, y).transform, color);
+            }
+        }
+    }
+
     pub fn writeln_text<G: Graphics<Texture=gfx_texture::Texture<gfx_device_gl::Resources>>>(text: &str,
         color: piston_window::types::Color, 
         transform: graphics::context::Context, 
@@ -93,47 +515,184 @@ impl Render {
         result
     } 
     
-    pub fn render_game_over_section(c: &graphics::Context, tetris: &Tetris, 
+    pub fn render_game_over_section(c: &graphics::Context, player: &Player, game_keys: &Bindings<Key>,
+        winner: bool,
         cache: &mut piston_window::Glyphs, 
         gl: &mut piston_window::G2d, 
         transform: graphics::context::Context) -> graphics::context::Context {
         let mut result: graphics::context::Context = transform;
-        result = Render::writeln_text(&"GAME OVER", ORANGE, result, c, cache, gl);
+        result = Render::writeln_text(if winner { "WINNER" } else { "GAME OVER" }, ORANGE, result, c, cache, gl);
 
-        result = Render::writeln_text(&"Press 'N' for a new game", ORANGE, result, c, cache, gl);
+        // the opponent's game in a network game is controlled from their own window
+        let keys = match player.keys {
+            Some(ref keys) => keys,
+            None => return result,
+        };
+        let game_key = |action: Action| get_key_names(game_keys, action);
+        let player_key = |action: Action| get_key_names(keys, action);
+
+        result = Render::writeln_text(&format!("Press {} for a new game, {} scores", game_key(Action::NewGame),
+            game_key(Action::HighScores)), ORANGE, result, c, cache, gl);
 
-        result = Render::writeln_text(&"Use arrow keys to move and rotate", ORANGE, result, c, cache, gl);
+        result = Render::writeln_text(&format!("Move {}/{}, down {}", player_key(Action::MoveLeft), player_key(Action::MoveRight),
+            player_key(Action::SoftDrop)), ORANGE, result, c, cache, gl);
         
-        result = Render::writeln_text(&"Press spacebar to drop", ORANGE, result, c, cache, gl);
+        result = Render::writeln_text(&format!("Rotate {}, {} CCW, {} 180", player_key(Action::RotateCW),
+            player_key(Action::RotateCCW), player_key(Action::Rotate180)), ORANGE, result, c, cache, gl);
 
-        result = Render::writeln_text(&format!("Press 'K' to decrease starting level ({})", tetris.get_starting_level()), 
+        result = Render::writeln_text(&format!("Drop {}, hold {}", player_key(Action::HardDrop), player_key(Action::Hold)),
             ORANGE, result, c, cache, gl);
 
-        result = Render::writeln_text(&"Press 'L' to increase starting level", 
+        result = Render::writeln_text(&format!("Press {}/{} to change level ({})", game_key(Action::LevelDown),
+            game_key(Action::LevelUp), player.tetris.get_starting_level()), ORANGE, result, c, cache, gl);
+
+        result = Render::writeln_text(&format!("{} switches the AI on or off, {} pauses", game_key(Action::ToggleAi),
+            game_key(Action::Pause)), ORANGE, result, c, cache, gl);
+
+        result = Render::writeln_text(&format!("{} perfect clears, {} openers", game_key(Action::PerfectClears), game_key(Action::Openers)),
             ORANGE, result, c, cache, gl);
+
+        result = Render::writeln_text(&format!("{} finesse, {} hints, {} keys", game_key(Action::FinesseFlash),
+            game_key(Action::Hints), game_key(Action::Rebind)), ORANGE, result, c, cache, gl);
         result
     }
 
+    // renders the prompt for the name of a player whose game made the high-score table
+    pub fn render_name_entry(c: &graphics::Context, name_entry: &NameEntry,
+        cache: &mut piston_window::Glyphs,
+        gl: &mut piston_window::G2d,
+        transform: graphics::context::Context) -> graphics::context::Context {
+        let mut result: graphics::context::Context = transform;
+        result = Render::writeln_text("GAME OVER", ORANGE, result, c, cache, gl);
+        result = Render::writeln_text(&format!("New high score: {}", name_entry.high_score.score), GREEN, result, c, cache, gl);
+        result = Render::writeln_text(&format!("Name: {}_", name_entry.high_score.name), ORANGE, result, c, cache, gl);
+        result = Render::writeln_text("Press enter to save", ORANGE, result, c, cache, gl);
+        result
+    }
+
+    // renders the high-score table of a mode and ruleset in place of the games
+    pub fn render_high_scores(c: &graphics::Context, title: &str, table: &[HighScore], highlight: Option<usize>,
+        close_help: &str,
+        cache: &mut piston_window::Glyphs,
+        gl: &mut piston_window::G2d) {
+        let mut transform = c.trans(LEFT_MARGIN * 2f64, TOP_MARGIN + LINE_HEIGHT);
+        transform = Render::writeln_text(title, ORANGE, transform, c, cache, gl);
+        transform = Render::writeln_text(&format!("{:>2}  {:<12} {:>7} {:>5} {:>5} {:>6}  {}",
+            "#", "Name", "Score", "Lines", "Level", "Time", "Date"), GRAY, transform, c, cache, gl);
+        if table.is_empty() {
+            transform = Render::writeln_text("No high scores yet", ORANGE, transform, c, cache, gl);
+        }
+        for (rank, high_score) in table.iter().enumerate() {
+            let color = if highlight == Some(rank) { GREEN } else { ORANGE };
+            let name: String = high_score.name.chars().take(MAX_NAME_LENGTH).collect();
+            transform = Render::writeln_text(&format!("{:>2}  {:<12} {:>7} {:>5} {:>5} {:>6}  {}",
+                rank + 1, name, high_score.score, high_score.lines, high_score.level,
+                high_scores::format_time(high_score.time), high_scores::format_date(high_score.date)), color, transform, c, cache, gl);
+        }
+        Render::writeln_text(close_help, ORANGE, transform.trans(0f64, LINE_HEIGHT), c, cache, gl);
+    }
+
+    // renders the prompt of the rebinding screen, asking for the keys of an action
+    pub fn render_rebinding(c: &graphics::Context, rebinding: &Rebinding, keys: &Bindings<Key>,
+        cache: &mut piston_window::Glyphs,
+        gl: &mut piston_window::G2d,
+        transform: graphics::context::Context) -> graphics::context::Context {
+        let mut result: graphics::context::Context = transform;
+        let action = Action::PLAYER_ACTIONS[rebinding.action];
+        result = Render::writeln_text(&format!("Keys for {}:", action.get_name()), ORANGE, result, c, cache, gl);
+        let mut pressed = Bindings::new();
+        pressed.bind(action, &rebinding.keys);
+        let text = if rebinding.keys.is_empty() { String::from("Press a key") } else { get_key_names(&pressed, action) };
+        result = Render::writeln_text(&text, GREEN, result, c, cache, gl);
+        if let Some(ref rejected) = rebinding.rejected {
+            result = Render::writeln_text(rejected, RED, result, c, cache, gl);
+        }
+        result = Render::writeln_text("Press a key twice to go on", ORANGE, result, c, cache, gl);
+        result = Render::writeln_text(&format!("Backspace keeps {}", get_key_names(keys, action)), ORANGE, result, c, cache, gl);
+        result
+    }
+
+    // renders a bar along the left side of the game board showing how many rows of garbage
+    // are waiting to be pushed onto the board
+    pub fn render_garbage_meter(c: &graphics::Context, 
+        gl: &mut piston_window::G2d, tetris: &Tetris, left_margin: f64) {
+        let rows = std::cmp::min(tetris.get_pending_garbage(), ROW_COUNT as u32) as f64;
+        if rows > 0f64 {
+            let rectangle = graphics::Rectangle::new(RED);
+            rectangle.draw([
+                left_margin + LEFT_MARGIN - GARBAGE_METER_WIDTH - 4f64,
+                TOP_MARGIN + (ROW_COUNT as f64 - rows) * CELL_SIZE,
+                GARBAGE_METER_WIDTH,
+                rows * CELL_SIZE,
+            ], &c.draw_state, c.transform, gl);
+        }
+    }
+
+    // renders the cells of each placement of a perfect clear, faded so the first placement stands out
+    pub fn render_perfect_clear(c: &graphics::Context,
+        gl: &mut piston_window::G2d, placements: &[Placement], left_margin: f64) {
+        for (index, placement) in placements.iter().enumerate() {
+            let mut color = get_shape_color(placement.shape_index);
+            color[3] = 0.6 / (index + 1) as f32;
+            Render::render_placement(c, gl, placement, color, left_margin);
+        }
+    }
+
+    // renders the cells of a placement on the game board
+    pub fn render_placement(c: &graphics::Context,
+        gl: &mut piston_window::G2d, placement: &Placement, color: [f32; 4], left_margin: f64) {
+        for point in placement.get_cells().iter() {
+            Render::render_board_cell(c, gl, point.x as i32, point.y as i32, color, left_margin);
+        }
+    }
+
+    // renders a single cell of the game board
+    pub fn render_board_cell(c: &graphics::Context,
+        gl: &mut piston_window::G2d, col: i32, row: i32, color: [f32; 4], left_margin: f64) {
+        let (x, y) = (col as f64 * CELL_SIZE, row as f64 * CELL_SIZE);
+        let transform = c.transform.trans(left_margin + LEFT_MARGIN, TOP_MARGIN).trans(x, y);
+        Render::render_cell(c, gl, transform, color);
+    }
+
+    // renders the outline of a single cell of the game board
+    pub fn render_board_cell_outline(c: &graphics::Context,
+        gl: &mut piston_window::G2d, col: i32, row: i32, color: [f32; 4], left_margin: f64) {
+        let (x, y) = (col as f64 * CELL_SIZE, row as f64 * CELL_SIZE);
+        let transform = c.transform.trans(left_margin + LEFT_MARGIN, TOP_MARGIN).trans(x, y);
+        let square = graphics::rectangle::square(1f64, 1f64, CELL_SIZE - 3f64);
+        graphics::Rectangle::new_border(color, 1.5).draw(square, &c.draw_state, transform, gl);
+    }
+
     // renders the game board cells e.g. the current shape, ghost shape, and all prior shapes that are
-    // fixed in place
+    // fixed in place. The empty cells of a guide placement, like the next part of an opener, are drawn
+    // as ghost cells, and the cells of a hint placement are outlined over the empty and ghost cells.
     pub fn render_game_board(c: &graphics::Context, 
-        gl: &mut piston_window::G2d, tetris: &Tetris) {
+        gl: &mut piston_window::G2d, tetris: &Tetris, guide: Option<&Placement>, hint: Option<&Placement>, left_margin: f64) {
         for col in 0..COL_COUNT as i32 {
             for row in 0..ROW_COUNT as i32 {
-                let cell = tetris.get_grid_cell(col, row);
-                if cell.cell_type != GridCellType::Void {
+                let mut cell = tetris.get_grid_cell(col, row);
+                let guided = guide.is_some_and(|placement| placement.get_cells().iter()
+                    .any(|point| point.x as i32 == col && point.y as i32 == row));
+                if cell.cell_type == GridCellType::Void && guided {
+                    cell.cell_type = GridCellType::Ghost;
+                }
+                if cell.cell_type == GridCellType::Void || cell.cell_type == GridCellType::Ghost {
+                    let hinted = hint.filter(|placement| placement.get_cells().iter()
+                        .any(|point| point.x as i32 == col && point.y as i32 == row));
+                    if let Some(placement) = hinted {
+                        cell = GridCell { cell_type: GridCellType::Hint, shape_index: placement.shape_index };
+                    }
+                }
+                if cell.cell_type == GridCellType::Hint {
+                    Render::render_board_cell_outline(c, gl, col, row, get_shape_color(cell.shape_index), left_margin);
+                } else if cell.cell_type != GridCellType::Void {
                     let color = match cell.cell_type {
                         GridCellType::Shape => get_shape_color(cell.shape_index),
                         GridCellType::Fixed => get_shape_color(cell.shape_index),
                         GridCellType::Ghost => DARK_GRAY,
-                        _ => {
-                            assert!(false);
-                            BLACK
-                        }
+                        _ => unreachable!(),
                     };
-                    let (x, y) = (col as f64 * CELL_SIZE, row as f64 * CELL_SIZE);
-                    let transform = c.transform.trans(LEFT_MARGIN, TOP_MARGIN).trans(x, y);
-                    Render::render_cell(&c, gl, transform, color);
+                    Render::render_board_cell(c, gl, col, row, color, left_margin);
                 }
             }
         }
@@ -144,115 +703,935 @@ impl App {
     fn render(&mut self, window: &mut PistonWindow, event: &impl piston_window::GenericEvent) {
         // so that we can access inside closure
         let use_cache = &mut self.glyphs;
-        let use_tetris = &self.tetris;
+        let use_players = &self.players;
+        let use_winner = self.winner;
+        let use_game_keys = &self.key_config.game;
+        let use_rebinding = self.rebinding.as_ref();
+        let use_name_entry = self.name_entries.first();
+        // finesse is only tracked for inputs applied directly to the games, not over the network
+        let use_finesse = self.network.is_none();
+        let use_high_scores = if self.show_high_scores {
+            let mode = self.get_mode();
+            Some((format!("HIGH SCORES - {} ({})", mode, self.ruleset), self.high_scores.get_table(mode, &self.ruleset),
+                format!("Press {} to close", get_key_names(&self.key_config.game, Action::HighScores))))
+        } else {
+            None
+        };
+        let use_high_score_rank = self.high_score_rank;
 
         window.draw_2d(event, |c, g, device| {
             // clear the viewport
             clear(BLACK, g);
 
-            // render the current score and level
-            let mut transform: graphics::context::Context = c.trans(STATUS_LEFT_MARGIN, STATUS_TOP_MARGIN);
-            transform = Render::writeln_text(&format!("Level: {}", use_tetris.get_level()), 
-                ORANGE, transform, &c, use_cache, g);
+            if let Some((ref title, table, ref close_help)) = use_high_scores {
+                Render::render_high_scores(&c, title, table, use_high_score_rank, close_help, use_cache, g);
+                use_cache.factory.encoder.flush(device);
+                return;
+            }
 
-            transform = Render::writeln_text(&format!("Score: {}", use_tetris.get_score()), ORANGE, transform, &c, use_cache, g);
+            for (index, player) in use_players.iter().enumerate() {
+                let use_tetris = &player.tetris;
 
-            transform = Render::render_next_shape(&c, g, use_tetris, transform);
+                // render the current score and level
+                let mut transform: graphics::context::Context = c.trans(player.left_margin + STATUS_LEFT_MARGIN, STATUS_TOP_MARGIN);
+                transform = Render::writeln_text(&format!("Level: {}", use_tetris.get_level()), 
+                    ORANGE, transform, &c, use_cache, g);
 
-            // render GAME OVER text if necessary
-            if use_tetris.get_game_over() {
-                /*transform =*/ Render::render_game_over_section(&c, use_tetris, use_cache, g, transform);
-            }
+                transform = Render::writeln_text(&format!("Score: {}", use_tetris.get_score()), ORANGE, transform, &c, use_cache, g);
+
+                transform = Render::writeln_text(&format!("Attack: {}", use_tetris.get_attack()), ORANGE, transform, &c, use_cache, g);
+
+                if use_finesse {
+                    transform = Render::writeln_text(&format!("Finesse faults: {}", player.finesse.get_faults()),
+                        ORANGE, transform, &c, use_cache, g);
+                }
+
+                // the shapes coming up are hidden with the board while the game is paused
+                let paused = use_tetris.get_paused();
+                if paused {
+                    transform = transform.trans(0f64, STATUS_PREVIEW_GRID_HEIGHT);
+                } else {
+                    Render::render_hold_shape(&c, g, use_tetris, transform);
+
+                    transform = Render::render_next_shape(&c, g, use_tetris, transform);
+                }
+
+                // render GAME OVER text if necessary
+                if let (Some(rebinding), Some(keys)) = (use_rebinding.filter(|rebinding| rebinding.player == index), player.keys.as_ref()) {
+                    Render::render_rebinding(&c, rebinding, keys, use_cache, g, transform);
+                } else if let Some(name_entry) = use_name_entry.filter(|name_entry| name_entry.player == index) {
+                    Render::render_name_entry(&c, name_entry, use_cache, g, transform);
+                } else if use_tetris.get_game_over() {
+                    /*transform =*/ Render::render_game_over_section(&c, player, use_game_keys,
+                        use_winner == Some(index), use_cache, g, transform);
+                } else if paused {
+                    transform = Render::writeln_text("PAUSED", ORANGE, transform, &c, use_cache, g);
+                    Render::writeln_text(&format!("Press {} to resume", get_key_names(use_game_keys, Action::Pause)),
+                        ORANGE, transform, &c, use_cache, g);
+                } else {
+                    if player.ai.is_some() {
+                        transform = Render::writeln_text("AI playing", ORANGE, transform, &c, use_cache, g);
+                    }
+                    if let Some(ref placements) = player.perfect_clear {
+                        transform = Render::writeln_text(&format!("Perfect clear in {}", placements.len()), ORANGE, transform, &c, use_cache, g);
+                    }
+                    if player.finesse_flash_time > 0.0 {
+                        if let Some(placement) = player.finesse.get_last_fault() {
+                            let keys: Vec<String> = placement.keys.iter().map(|&(input, count)| match input {
+                                // left or right held to the wall
+                                Input::Left | Input::Right if count > 1 => format!("DAS {}", get_input_name(input)),
+                                _ => get_input_name(input).to_string(),
+                            }).collect();
+                            transform = Render::writeln_text(&format!("Finesse: {}", keys.join(" ")), RED, transform, &c, use_cache, g);
+                        }
+                    }
+                    if let Some((ref progress, _)) = player.opener {
+                        let text = if !progress.deviations.is_empty() {
+                            format!("Left {} at col {}", progress.opener.name, progress.deviations[0].x + 1)
+                        } else if progress.is_complete() {
+                            format!("{} built", progress.opener.name)
+                        } else {
+                            format!("{} {}/{}", progress.opener.name, progress.matched, progress.total)
+                        };
+                        transform = Render::writeln_text(&text, ORANGE, transform, &c, use_cache, g);
+                    }
+                    if let Some((_, ref reasons)) = player.hint {
+                        // the most important reasons, one per line so they fit the status column
+                        for (index, reason) in reasons.iter().take(2).enumerate() {
+                            let text = if index == 0 { format!("Hint: {}", reason) } else { format!("      {}", reason) };
+                            transform = Render::writeln_text(&text, ORANGE, transform, &c, use_cache, g);
+                        }
+                    }
+                }
 
-            // draw a white border around the game board
-            let rect_border = graphics::Rectangle::new_border(LIGHT_GRAY, 1.5);
-            rect_border.draw([
-                LEFT_MARGIN - 2f64,
-                TOP_MARGIN - 2f64,
-                (CELL_SIZE * COL_COUNT as f64) + 3f64,
-                (CELL_SIZE * ROW_COUNT as f64) + 3f64,
-            ], &c.draw_state, c.transform, g);
+                // draw a white border around the game board
+                let rect_border = graphics::Rectangle::new_border(LIGHT_GRAY, 1.5);
+                rect_border.draw([
+                    player.left_margin + LEFT_MARGIN - 2f64,
+                    TOP_MARGIN - 2f64,
+                    (CELL_SIZE * COL_COUNT as f64) + 3f64,
+                    (CELL_SIZE * ROW_COUNT as f64) + 3f64,
+                ], &c.draw_state, c.transform, g);
 
-            Render::render_game_board(&c, g, use_tetris);
+                if paused {
+                    let transform = c.trans(player.left_margin + LEFT_MARGIN + CELL_SIZE * 3f64, TOP_MARGIN + CELL_SIZE * ROW_COUNT as f64 / 2f64);
+                    Render::writeln_text("PAUSED", ORANGE, transform, &c, use_cache, g);
+                    continue;
+                }
+
+                if let Some(ref placements) = player.perfect_clear {
+                    Render::render_perfect_clear(&c, g, placements, player.left_margin);
+                }
+                // the next placement of the opener is drawn as a ghost shape
+                let guide = player.opener.as_ref().and_then(|(_, placement)| placement.as_ref());
+                let hint = player.hint.as_ref().map(|(placement, _)| placement);
+                Render::render_game_board(&c, g, use_tetris, guide, hint, player.left_margin);
+
+                Render::render_garbage_meter(&c, g, use_tetris, player.left_margin);
+            }
 
             use_cache.factory.encoder.flush(device);
         });
     }
     
     fn update(&mut self, args: &UpdateArgs) {
-        if self.tetris.get_game_over() {
-            self.elapsed_time = 0.0;
-        } else {
-            // Here we increment the time elapsed between update()'s
-            self.elapsed_time += args.dt;
-            // if the elapsed time is now greater than the time allotted between ticks, then invoke tetris.tick()
-            if self.elapsed_time > self.tetris.get_tick_time() as f64 {
-                self.elapsed_time = 0.0;
-                self.tetris.tick();
+        self.update_overlays();
+
+        // local games wait while the keys are rebound, and every timer stops while they're paused
+        if (self.rebinding.is_some() && self.network.is_none()) || self.is_paused() {
+            return;
+        }
+
+        if self.network.is_some() {
+            // the keys held by the local player repeat into the next frame's input, at most once a
+            // frame for each input since a frame only records which inputs were pressed
+            let repeated = {
+                let player = &mut self.players[0];
+                if player.tetris.get_game_over() {
+                    Vec::new()
+                } else {
+                    player.auto_repeat.update(args.dt, player.tetris.get_tick_time() as f64)
+                }
+            };
+            for input in repeated {
+                self.apply_repeated_input(0, input);
+            }
+            self.update_network(args.dt);
+            return;
+        }
+
+        for index in 0..self.players.len() {
+            let tick = {
+                let player = &mut self.players[index];
+                if player.tetris.get_game_over() || player.is_remote() {
+                    player.elapsed_time = 0.0;
+                    false
+                } else {
+                    // Here we increment the time elapsed between update()'s
+                    player.elapsed_time += args.dt;
+                    player.game_time += args.dt;
+                    // if the elapsed time is now greater than the time allotted between ticks, then invoke tetris.tick()
+                    if player.elapsed_time > player.tetris.get_tick_time() as f64 {
+                        player.elapsed_time = 0.0;
+                        true
+                    } else {
+                        false
+                    }
+                }
+            };
+            if tick {
+                self.apply_input(index, Input::Tick);
+            }
+
+            let repeated = {
+                let player = &mut self.players[index];
+                if player.tetris.get_game_over() || player.ai.is_some() {
+                    Vec::new()
+                } else {
+                    player.auto_repeat.update(args.dt, player.tetris.get_tick_time() as f64)
+                }
+            };
+            for input in repeated {
+                self.apply_repeated_input(index, input);
+            }
+
+            let ai_input = {
+                let player = &mut self.players[index];
+                player.finesse_flash_time = (player.finesse_flash_time - args.dt).max(0.0);
+                player.ai_elapsed_time += args.dt;
+                match player.ai {
+                    Some(ref mut ai) if player.ai_elapsed_time > AI_INPUT_TIME => {
+                        player.ai_elapsed_time = 0.0;
+                        ai.next_input(&player.tetris)
+                    },
+                    _ => None,
+                }
+            };
+            if let Some(input) = ai_input {
+                if input == Input::HardDrop {
+                    self.players[index].elapsed_time = 0.0;
+                }
+                self.apply_input(index, input);
+            }
+        }
+
+        // in a versus game, the last player standing wins and the other games end
+        if self.players.len() > 1 && self.winner.is_none() {
+            let mut playing = (0..self.players.len()).filter(|index| !self.players[*index].tetris.get_game_over());
+            if let (Some(index), None) = (playing.next(), playing.next()) {
+                self.winner = Some(index);
+                self.players[index].tetris.end_game();
+            }
+        }
+
+        self.record_high_scores();
+    }
+
+    /// Returns the mode of the high-score table the games are recorded in
+    fn get_mode(&self) -> &'static str {
+        if self.players.len() > 1 { "versus" } else { "marathon" }
+    }
+
+    /// Checks the score of each local game that just ended against the high scores, and asks
+    /// for the player's name if it made the table. Games the AI played any part of don't count.
+    fn record_high_scores(&mut self) {
+        let mode = self.get_mode();
+        for index in 0..self.players.len() {
+            let player = &mut self.players[index];
+            if player.is_remote() || !player.tetris.get_game_over() || player.score_recorded {
+                continue;
+            }
+            player.score_recorded = true;
+            let tetris = &player.tetris;
+            if !player.ai_played && self.high_scores.qualifies(mode, &self.ruleset, tetris.get_score()) {
+                self.name_entries.push(NameEntry {
+                    player: index,
+                    high_score: HighScore {
+                        name: self.high_scores.get_last_name().to_string(),
+                        score: tetris.get_score(),
+                        lines: tetris.get_rows_completed(),
+                        level: tetris.get_level(),
+                        time: player.game_time,
+                        date: high_scores::now(),
+                    },
+                });
             }
         }
     }
 
+    /// Updates the perfect clear and opener overlays of each local player, each time a new
+    /// shape is played or held. The perfect clear and hint searches take a while, so they run on
+    /// other threads, and their overlays are shown once they're done.
+    fn update_overlays(&mut self) {
+        for player in self.players.iter_mut().filter(|player| !player.is_remote()) {
+            if let Some(result) = player.perfect_clear_search.as_ref().and_then(|search| search.try_recv().ok()) {
+                player.perfect_clear = result;
+                player.perfect_clear_search = None;
+            }
+            if let Some(result) = player.hint_search.as_ref().and_then(|search| search.try_recv().ok()) {
+                player.hint = result;
+                player.hint_search = None;
+            }
+
+            let state = (player.tetris.get_shape_count(), player.tetris.get_hold_shape_index());
+            if player.overlay_state == Some(state) {
+                continue;
+            }
+            player.overlay_state = Some(state);
+            // the searches for the last shape are dropped, and their results ignored
+            player.perfect_clear = None;
+            player.perfect_clear_search = self.perfect_clear_solver.clone().map(|solver| {
+                let tetris = player.tetris.clone();
+                spawn_search(move || solver.solve(&tetris))
+            });
+            player.opener = if self.opener_trainer {
+                // follow the best opener that the shapes in play can build, or show the best
+                // match if there's none
+                let tetris = &player.tetris;
+                let candidates = openers::recognize_openers(tetris);
+                let next = candidates.iter()
+                    .filter_map(|progress| progress.next_placement(tetris).map(|placement| (progress.clone(), Some(placement))))
+                    .next();
+                next.or_else(|| candidates.into_iter().next().map(|progress| (progress, None)))
+            } else {
+                None
+            };
+            player.hint = None;
+            player.hint_search = if player.tetris.get_game_over() {
+                None
+            } else {
+                self.hint_planner.clone().map(|mut planner| {
+                    let tetris = player.tetris.clone();
+                    spawn_search(move || planner.choose(&tetris).map(|placement| {
+                        let reasons = ai::explain_placement(&tetris, &placement);
+                        (placement, reasons)
+                    }))
+                })
+            };
+        }
+    }
+
+    /// Clears the overlays of every player, so they're updated with the next frame
+    fn reset_overlays(&mut self) {
+        for player in self.players.iter_mut() {
+            player.perfect_clear = None;
+            player.perfect_clear_search = None;
+            player.opener = None;
+            player.hint = None;
+            player.hint_search = None;
+            player.overlay_state = None;
+        }
+    }
+
+    /// Simulates the frames of a network game, and shows the resulting games
+    fn update_network(&mut self, dt: f64) {
+        let connected = {
+            let network = self.network.as_mut().unwrap();
+            while let Some(message) = network.connection.receive() {
+                if let Message::Frame { frame, input } = message {
+                    network.session.add_remote_input(frame, input);
+                }
+            }
+
+            let frame_time = 1.0 / FRAMES_PER_SECOND as f64;
+            network.elapsed_time += dt;
+            while network.elapsed_time >= frame_time && network.session.can_advance() {
+                network.elapsed_time -= frame_time;
+                let input = network.input;
+                network.input = FrameInput::default();
+                let frame = network.session.advance(input);
+                network.connection.send(&Message::Frame { frame, input });
+            }
+            // don't try to catch up on frames while waiting for the opponent
+            network.elapsed_time = network.elapsed_time.min(frame_time);
+
+            // the local player is shown first
+            let state = network.session.get_state();
+            let local_index = network.session.get_local_index();
+            self.players[0].tetris = state.get_games()[local_index].clone();
+            self.players[1].tetris = state.get_games()[1 - local_index].clone();
+            self.winner = state.get_winner().map(|index| if index == local_index { 0 } else { 1 });
+            network.connection.is_connected()
+        };
+
+        // the opponent disconnecting ends their game, and play continues locally
+        if !connected {
+            self.network = None;
+            self.players[1].tetris.end_game();
+        }
+    }
+
+    /// Applies an input to a player's game
+    fn apply_input(&mut self, index: usize, input: Input) {
+        if self.network.is_none() {
+            let player = &mut self.players[index];
+            player.finesse.press(&player.tetris, input);
+        }
+        self.apply_repeated_input(index, input);
+    }
+
+    /// Applies an input repeated by a key held down. A held key only counts as one key for
+    /// finesse, so the input isn't recorded by the finesse tracker.
+    fn apply_repeated_input(&mut self, index: usize, input: Input) {
+        if let Some(ref mut network) = self.network {
+            // the input is simulated with the next frame
+            network.input.press(input);
+            return;
+        }
+        let player = &mut self.players[index];
+        if let Some(result) = player.tetris.apply_input(input) {
+            if player.finesse.lock(&result) && self.flash_finesse {
+                player.finesse_flash_time = FINESSE_FLASH_TIME;
+            }
+            self.send_garbage(index, result.attack);
+        }
+    }
+
+    /// Sends garbage rows from one player to every other player. The rows first cancel any garbage
+    /// the sending player has waiting, and only the rows left over are sent.
+    fn send_garbage(&mut self, from_index: usize, rows: u32) {
+        let rows = self.players[from_index].tetris.cancel_garbage(rows);
+        if rows > 0 {
+            let hole_col = rand::thread_rng().gen_range(0, COL_COUNT as i32);
+            for (index, player) in self.players.iter_mut().enumerate() {
+                // an opponent who disconnected from a network game doesn't receive garbage
+                if index != from_index && !player.is_remote() {
+                    player.tetris.queue_garbage(rows, hole_col);
+                }
+            }
+        }
+    }
+
+    /// Starts a new game for each player whose game is over
+    fn start_games(&mut self) {
+        if let Some(ref mut network) = self.network {
+            // both games start together with the next frame
+            network.input.press_new_game();
+            return;
+        }
+        for player in self.players.iter_mut() {
+            if !player.is_remote() {
+                if player.tetris.get_game_over() {
+                    player.game_time = 0.0;
+                    player.ai_played = player.ai.is_some();
+                    player.score_recorded = false;
+                }
+                player.tetris.start_game();
+                player.finesse.reset();
+                player.auto_repeat.release_all();
+            }
+        }
+        self.winner = None;
+        self.show_high_scores = false;
+        self.high_scores_paused = false;
+    }
+
+    /// Returns the built-in AI, or starts the external bot if one was given
+    fn new_planner(&self) -> Option<Box<dyn Planner>> {
+        match self.bot_command {
+            Some(ref command) => match TbpBot::spawn(command) {
+                Ok(bot) => {
+                    println!("Playing with {}", bot.get_name());
+                    Some(Box::new(bot))
+                },
+                Err(error) => {
+                    eprintln!("Unable to start the bot: {}", error);
+                    None
+                },
+            },
+            None => Some(Box::new(BeamSearch::with_weights(self.weights.clone()))),
+        }
+    }
+
     fn handle_key_input(&mut self, key: keyboard::Key) {
-        match key {
-            Key::Left => { 
-                let col: i32 = self.tetris.get_col();
-                self.tetris.set_col(col - 1);
+        if self.rebinding.is_some() {
+            self.handle_rebinding_key(key);
+            return;
+        }
+        if !self.name_entries.is_empty() {
+            self.handle_name_entry_key(key);
+            return;
+        }
+        match self.key_config.game.action(key) {
+            Some(action) => self.handle_game_action(action),
+            None => {
+                for index in 0..self.players.len() {
+                    if let Some(action) = self.players[index].keys.as_ref().and_then(|keys| keys.action(key)) {
+                        self.press_player_action(index, action);
+                    }
+                }
             },
+        }
+    }
 
-            Key::Right => { 
-                let col: i32 = self.tetris.get_col();
-                self.tetris.set_col(col + 1);
+    /// Handles the actions that apply to every player, like starting a new game
+    fn handle_game_action(&mut self, action: Action) {
+        match action {
+            Action::NewGame => { 
+                self.start_games();
             },
 
-            Key::Up => { 
-                self.tetris.rotate(true);
+            Action::Pause => {
+                let paused = !self.is_paused();
+                self.set_paused(paused);
+                // the player chose whether the games are paused, so closing the table leaves them be
+                self.high_scores_paused = false;
             },
 
-            Key::Down => { 
-                let row: i32 = self.tetris.get_row() + 1;
-                self.tetris.set_row(row);
+            Action::HighScores => {
+                // the games are hidden behind the table, so they're paused
+                self.show_high_scores = !self.show_high_scores;
+                if self.show_high_scores {
+                    self.high_scores_paused = !self.is_paused();
+                    self.set_paused(true);
+                    // games that are over can't be paused, so there's nothing to resume
+                    self.high_scores_paused &= self.is_paused();
+                } else {
+                    if self.high_scores_paused {
+                        self.set_paused(false);
+                    }
+                    self.high_scores_paused = false;
+                    self.high_score_rank = None;
+                }
             },
 
-            Key::Space => { 
-                let mut row: i32 = self.tetris.get_row() + 1;
-                while self.tetris.set_row(row) {
-                    row += 1;
+            Action::LevelDown => { 
+                for player in self.players.iter_mut() {
+                    if player.tetris.get_starting_level() > 0 {
+                        let new_level: u32 = player.tetris.get_starting_level() - 1;
+                        player.tetris.set_starting_level(new_level);
+                    }
+                }
+            },
+
+            Action::LevelUp => { 
+                for player in self.players.iter_mut() {
+                    if player.tetris.get_starting_level() < 30 {
+                        let new_level: u32 = player.tetris.get_starting_level() + 1;
+                        player.tetris.set_starting_level(new_level);
+                    }
+                }
+            },
+
+            // the AI plays for the single player, or the player on the right of a versus game.
+            // Network games only simulate the inputs pressed by the players.
+            Action::ToggleAi if self.network.is_none() => {
+                if let Some(index) = self.players.iter().rposition(|player| !player.is_remote()) {
+                    self.players[index].auto_repeat.release_all();
+                    self.players[index].ai = match self.players[index].ai {
+                        Some(_) => None,
+                        None => self.new_planner().map(AiPlayer::new),
+                    };
+                    if self.players[index].ai.is_some() {
+                        self.players[index].ai_played = true;
+                    }
                 }
-                // hard drop immediately spawns next shape
-                self.tetris.tick();
-                self.elapsed_time = 0.0;
             },
 
-            Key::N => { 
-                self.tetris.start_game();
+            Action::PerfectClears => {
+                // the overlay shows the perfect clears found for every local player
+                self.perfect_clear_solver = match self.perfect_clear_solver {
+                    Some(_) => None,
+                    None => Some(PerfectClearSolver::default()),
+                };
+                self.reset_overlays();
             },
 
-            Key::K => { 
-                if self.tetris.get_starting_level() > 0 {
-                    let new_level: u32 = self.tetris.get_starting_level() - 1;
-                    self.tetris.set_starting_level(new_level); 
+            Action::FinesseFlash => {
+                self.flash_finesse = !self.flash_finesse;
+                for player in self.players.iter_mut() {
+                    player.finesse_flash_time = 0.0;
                 }
             },
 
-            Key::L => { 
-                if self.tetris.get_starting_level() < 30 {
-                    let new_level: u32 = self.tetris.get_starting_level() + 1;
-                    self.tetris.set_starting_level(new_level); 
+            Action::Openers => {
+                // the opener trainer shows the opener each local player is building
+                self.opener_trainer = !self.opener_trainer;
+                self.reset_overlays();
+            },
+
+            Action::Hints => {
+                // the hint shows where the AI would place the current shape of every local player
+                self.hint_planner = match self.hint_planner {
+                    Some(_) => None,
+                    None => Some(BeamSearch::with_weights(self.weights.clone())),
+                };
+                self.reset_overlays();
+            },
+
+            Action::Rebind => {
+                if let Some(player) = self.players.iter().position(|player| !player.is_remote()) {
+                    for player in self.players.iter_mut() {
+                        player.auto_repeat.release_all();
+                    }
+                    self.rebinding = Some(Rebinding { player, action: 0, keys: Vec::new(), rejected: None });
                 }
             },
 
-            _ => { }
+            _ => {},
+        }
+    }
+
+    /// Returns true while the local games are paused
+    fn is_paused(&self) -> bool {
+        self.players.iter().any(|player| player.tetris.get_paused())
+    }
+
+    /// Pauses or resumes every local game that isn't over. Network games keep running, since the
+    /// opponent's game can't be paused.
+    fn set_paused(&mut self, paused: bool) {
+        if self.network.is_some() {
+            return;
+        }
+        for player in self.players.iter_mut().filter(|player| !player.is_remote()) {
+            player.tetris.set_paused(paused);
+            player.auto_repeat.release_all();
+        }
+    }
+
+    /// Handles a key or button pressed for a player action
+    fn press_player_action(&mut self, index: usize, action: Action) {
+        if self.players[index].ai.is_some() || self.players[index].tetris.get_paused() {
+            return;
+        }
+        // keys held down are repeated by update(), rather than by the operating system
+        if let Some(input) = action.input().filter(|input| self.players[index].auto_repeat.press(*input)) {
+            if input == Input::HardDrop {
+                // hard drop immediately spawns next shape
+                self.players[index].elapsed_time = 0.0;
+            }
+            self.apply_input(index, input);
+        }
+    }
+
+    fn release_player_action(&mut self, index: usize, action: Action) {
+        if let Some(input) = action.input() {
+            self.players[index].auto_repeat.release(input);
         }
     }
+
+    /// Handles the keys pressed while a player enters their name for a high score. The name itself
+    /// is typed with text events, see handle_text().
+    fn handle_name_entry_key(&mut self, key: keyboard::Key) {
+        match key {
+            Key::Backspace => {
+                self.name_entries[0].high_score.name.pop();
+            },
+            Key::Return | Key::NumPadEnter => {
+                let NameEntry { player, mut high_score } = self.name_entries.remove(0);
+                if high_score.name.trim().is_empty() {
+                    high_score.name = format!("Player {}", player + 1);
+                }
+                let mode = self.get_mode();
+                self.high_score_rank = self.high_scores.add(mode, &self.ruleset, high_score);
+                // the other players haven't typed their names yet, so they're only asked if their
+                // scores still make the table with this one added
+                let (high_scores, ruleset) = (&self.high_scores, &self.ruleset);
+                self.name_entries.retain(|name_entry| high_scores.qualifies(mode, ruleset, name_entry.high_score.score));
+                if let Some(ref path) = self.high_scores_path {
+                    if let Err(error) = self.high_scores.save(path) {
+                        eprintln!("Unable to save the high scores to {}: {}", path.display(), error);
+                    }
+                }
+                // the table is shown once every player has entered their name
+                self.show_high_scores = self.name_entries.is_empty();
+            },
+            _ => {},
+        }
+    }
+
+    /// Adds the text typed to the name being entered for a high score
+    fn handle_text(&mut self, text: &str) {
+        if let Some(name_entry) = self.name_entries.first_mut() {
+            let name = &mut name_entry.high_score.name;
+            for character in text.chars().filter(|character| !character.is_control()) {
+                if name.chars().count() < MAX_NAME_LENGTH {
+                    name.push(character);
+                }
+            }
+        }
+    }
+
+    /// Binds the keys pressed on the rebinding screen. Each key pressed is added to the keys of the
+    /// action, and pressing one of them again moves on to the next action. Backspace keeps the keys
+    /// the action already has. Keys of the game actions or of another local player are refused.
+    /// Once every local player's keys are bound, they're saved.
+    fn handle_rebinding_key(&mut self, key: keyboard::Key) {
+        let player = self.rebinding.as_ref().unwrap().player;
+        let taken = self.key_config.game.action(key).map(|action| format!("{:?} is taken by {}", key, action.get_name()))
+            .or_else(|| self.players.iter().enumerate()
+                .filter(|(index, other)| *index != player && !other.is_remote())
+                .find_map(|(index, other)| other.keys.as_ref().and_then(|keys| keys.action(key))
+                    .map(|action| format!("{:?} is taken by {} of player {}", key, action.get_name(), index + 1))));
+        let (index, done) = {
+            let rebinding = self.rebinding.as_mut().unwrap();
+            let action = Action::PLAYER_ACTIONS[rebinding.action];
+            rebinding.rejected = None;
+            let done = if key == Key::Backspace {
+                true
+            } else if taken.is_some() {
+                rebinding.rejected = taken;
+                false
+            } else if rebinding.keys.contains(&key) {
+                let keys = self.players[rebinding.player].keys.as_mut().unwrap();
+                keys.bind(action, &rebinding.keys);
+                true
+            } else {
+                rebinding.keys.push(key);
+                false
+            };
+            (rebinding.player, done)
+        };
+        if !done {
+            return;
+        }
+
+        let rebinding = self.rebinding.take().unwrap();
+        if rebinding.action + 1 < Action::PLAYER_ACTIONS.len() {
+            self.rebinding = Some(Rebinding { player: index, action: rebinding.action + 1, keys: Vec::new(), rejected: None });
+            return;
+        }
+        if let Some(next) = (index + 1..self.players.len()).find(|next| !self.players[*next].is_remote()) {
+            self.rebinding = Some(Rebinding { player: next, action: 0, keys: Vec::new(), rejected: None });
+            return;
+        }
+
+        // every local player's keys are bound, so they're saved
+        let local: Vec<Bindings<Key>> = self.players.iter().filter_map(|player| player.keys.clone()).collect();
+        if let [ref one, ref two] = local[..] {
+            self.key_config.player_one = one.clone();
+            self.key_config.player_two = two.clone();
+        } else if let Some(keys) = local.into_iter().next() {
+            self.key_config.single_player = keys;
+        }
+        if let Err(error) = self.key_config.save(&self.key_config_path) {
+            eprintln!("Unable to save the keys to {}: {}", self.key_config_path.display(), error);
+        }
+    }
+
+    fn handle_key_release(&mut self, key: keyboard::Key) {
+        for index in 0..self.players.len() {
+            if let Some(action) = self.players[index].keys.as_ref().and_then(|keys| keys.action(key)) {
+                self.release_player_action(index, action);
+            }
+        }
+    }
+
+    /// Returns the index of the local player a gamepad plays for. In a versus game the first
+    /// gamepad plays for the left player and the second for the right player.
+    fn gamepad_player(&self, id: u32) -> Option<usize> {
+        let local: Vec<usize> = (0..self.players.len()).filter(|index| !self.players[*index].is_remote()).collect();
+        local.get(id as usize % local.len().max(1)).cloned()
+    }
+
+    /// Returns the state of the sticks and D-pad of a gamepad
+    fn gamepad_state(&mut self, id: u32) -> &mut GamepadState {
+        let index = match self.gamepads.iter().position(|(gamepad_id, _)| *gamepad_id == id) {
+            Some(index) => index,
+            None => {
+                self.gamepads.push((id, GamepadState::new()));
+                self.gamepads.len() - 1
+            },
+        };
+        &mut self.gamepads[index].1
+    }
+
+    /// Handles the actions pressed and released on a gamepad, like keys. Gamepads don't press
+    /// anything while the keys are being rebound.
+    fn handle_gamepad_actions(&mut self, id: u32, actions: Vec<(Action, bool)>) {
+        let index = match self.gamepad_player(id) {
+            Some(index) => index,
+            None => return,
+        };
+        for (action, pressed) in actions {
+            if !pressed {
+                self.release_player_action(index, action);
+            } else if self.rebinding.is_none() && self.name_entries.is_empty() {
+                if action.input().is_some() {
+                    self.press_player_action(index, action);
+                } else {
+                    self.handle_game_action(action);
+                }
+            }
+        }
+    }
+
+    fn handle_gamepad_button(&mut self, button: ControllerButton, pressed: bool) {
+        if let Some(action) = self.key_config.gamepad.buttons.action(button.button) {
+            self.handle_gamepad_actions(button.id, vec![(action, pressed)]);
+        }
+    }
+
+    fn handle_gamepad_hat(&mut self, hat: ControllerHat, pressed: bool) {
+        let directions: &[Direction] = match hat.state {
+            _ if !pressed => &[],
+            HatState::Up => &[Direction::Up],
+            HatState::Down => &[Direction::Down],
+            HatState::Left => &[Direction::Left],
+            HatState::Right => &[Direction::Right],
+            HatState::LeftUp => &[Direction::Left, Direction::Up],
+            HatState::LeftDown => &[Direction::Left, Direction::Down],
+            HatState::RightUp => &[Direction::Right, Direction::Up],
+            HatState::RightDown => &[Direction::Right, Direction::Down],
+            HatState::Centered => &[],
+        };
+        let bindings = self.key_config.gamepad.clone();
+        let actions = self.gamepad_state(hat.id).set_d_pad(&bindings, directions);
+        self.handle_gamepad_actions(hat.id, actions);
+    }
+
+    fn handle_gamepad_axis(&mut self, args: ControllerAxisArgs) {
+        let bindings = self.key_config.gamepad.clone();
+        let actions = self.gamepad_state(args.id).move_axis(&bindings, args.axis, args.position);
+        self.handle_gamepad_actions(args.id, actions);
+    }
+
+    /// Handles an event read from the gamepads with gilrs, for window backends that don't report
+    /// gamepads. Buttons are numbered like an Xbox style gamepad, and the D-pad may be reported
+    /// as buttons or as a pair of axes.
+    #[cfg(feature = "gamepad")]
+    fn handle_gamepad_event(&mut self, event: gilrs::Event) {
+        let id = usize::from(event.id) as u32;
+        let bindings = self.key_config.gamepad.clone();
+        let actions = match event.event {
+            gilrs::EventType::ButtonPressed(button, _) | gilrs::EventType::ButtonReleased(button, _) => {
+                let pressed = matches!(event.event, gilrs::EventType::ButtonPressed(..));
+                match gamepad_direction(button) {
+                    Some(direction) => self.gamepad_state(id).press_d_pad(&bindings, direction, pressed),
+                    None => gamepad_button(button)
+                        .and_then(|button| bindings.buttons.action(button))
+                        .map_or_else(Vec::new, |action| vec![(action, pressed)]),
+                }
+            },
+            gilrs::EventType::AxisChanged(gilrs::Axis::DPadX, position, _) => {
+                let state = self.gamepad_state(id);
+                let mut actions = state.press_d_pad(&bindings, Direction::Left, position <= -0.5);
+                actions.extend(state.press_d_pad(&bindings, Direction::Right, position >= 0.5));
+                actions
+            },
+            gilrs::EventType::AxisChanged(gilrs::Axis::DPadY, position, _) => {
+                let state = self.gamepad_state(id);
+                let mut actions = state.press_d_pad(&bindings, Direction::Down, position <= -0.5);
+                actions.extend(state.press_d_pad(&bindings, Direction::Up, position >= 0.5));
+                actions
+            },
+            gilrs::EventType::AxisChanged(axis, position, _) => match gamepad_axis(axis) {
+                // the vertical axes point up, where the bindings expect them to point down
+                Some(number) if number % 2 == 1 && number < 4 =>
+                    self.gamepad_state(id).move_axis(&bindings, number, -position as f64),
+                Some(number) => self.gamepad_state(id).move_axis(&bindings, number, position as f64),
+                None => Vec::new(),
+            },
+            gilrs::EventType::Disconnected => self.gamepad_state(id).release_all(),
+            _ => Vec::new(),
+        };
+        self.handle_gamepad_actions(id, actions);
+    }
+
+    /// Pauses the games when the window loses focus, and releases every key since the key releases
+    /// go to another window
+    fn handle_focus(&mut self, focused: bool) {
+        if !focused {
+            self.set_paused(true);
+            for player in self.players.iter_mut() {
+                player.auto_repeat.release_all();
+            }
+            for (_, gamepad) in self.gamepads.iter_mut() {
+                gamepad.release_all();
+            }
+        }
+    }
+}
+
+/// Prints a problem with the command line or the files it names, and exits with a failure status
+fn exit_with_error(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
 }
 
 fn main() {
-    start_app();
+    let args: Vec<String> = std::env::args().collect();
+    let arg_value = |name: &str| args.iter().position(|arg| arg == name)
+        .map(|index| args.get(index + 1).unwrap_or_else(|| exit_with_error(&format!("Missing value for {}", name))).clone());
+    // pass --versus to play a two player game side by side
+    let versus = args.iter().any(|arg| arg == "--versus");
+    // pass --attack-table tetrio to change how much garbage is sent to the opponent
+    // the name of the attack table is also the ruleset the high scores are kept for
+    let ruleset = arg_value("--attack-table").unwrap_or_else(|| String::from("guideline"));
+    let attack_table = AttackTable::from_name(&ruleset)
+        .unwrap_or_else(|| exit_with_error(&format!("Unknown attack table '{}'", ruleset)));
+    // pass --level n to start games at level n, which the host of a network game also sends to the opponent
+    let starting_level: u32 = arg_value("--level")
+        .map_or(0, |value| value.parse().unwrap_or_else(|_| exit_with_error("--level must be a number")));
+    // pass --host address or --join address to play an opponent over the network
+    let host = arg_value("--host");
+    let connection = if let Some(ref address) = host {
+        println!("Waiting for an opponent to join {}", address);
+        Some(Connection::host(address))
+    } else {
+        arg_value("--join").map(|address| Connection::join(&address))
+    };
+    let network = connection.map(|connection| {
+        match connection.and_then(|connection| NetworkGame::new(connection, host.as_ref().map(|_| starting_level), &attack_table)) {
+            Ok(network) => network,
+            Err(error) => exit_with_error(&format!("Unable to start a game with the opponent: {}", error)),
+        }
+    });
+    // pass --bot command to let 'I' switch on an external bot speaking the Tetris Bot Protocol
+    let bot_command = arg_value("--bot").map(|command| command.split_whitespace().map(String::from).collect());
+    // pass --weights file to play the built-in AI with weights saved by the tune binary
+    let weights = match arg_value("--weights") {
+        Some(path) => Weights::load(Path::new(&path))
+            .unwrap_or_else(|error| exit_with_error(&format!("Unable to load {}: {}", path, error))),
+        None => Weights::default(),
+    };
+    // pass --das, --arr (in milliseconds) and --sdf to change how held keys repeat
+    let handling_value = |name: &str, default: f64| arg_value(name)
+        .map_or(default, |value| value.parse().unwrap_or_else(|_| exit_with_error(&format!("{} must be a number", name))));
+    let default_handling = Handling::default();
+    let handling = Handling {
+        das: handling_value("--das", default_handling.das),
+        arr: handling_value("--arr", default_handling.arr),
+        soft_drop_factor: handling_value("--sdf", default_handling.soft_drop_factor),
+    };
+    // pass --keys file to load the keys from another file than keys.json, which is also where rebound keys are saved
+    let key_config_path = PathBuf::from(arg_value("--keys").unwrap_or_else(|| String::from("keys.json")));
+    // pass --high-scores file to keep the high scores in another file than the one in the user's data directory
+    let high_scores_path = arg_value("--high-scores").map(PathBuf::from).or_else(HighScores::default_path);
+    start_app(versus, starting_level, &attack_table, ruleset, network, bot_command, weights, handling, key_config_path, high_scores_path);
 }
 
-fn start_app() {
-    let mut window: PistonWindow = WindowSettings::new("Piston Tetris", [1024, 768]).
+#[allow(clippy::too_many_arguments)]
+fn start_app(versus: bool, starting_level: u32, attack_table: &AttackTable, ruleset: String, network: Option<NetworkGame>, bot_command: Option<Vec<String>>,
+    weights: Weights, handling: Handling, key_config_path: PathBuf, high_scores_path: Option<PathBuf>) {
+    let key_config = KeyConfig::load(&key_config_path)
+        .unwrap_or_else(|error| exit_with_error(&format!("Unable to load {}: {}", key_config_path.display(), error)));
+    // a high-score file that can't be read isn't overwritten, the scores just aren't saved this time
+    let (high_scores, high_scores_path) = match high_scores_path {
+        Some(path) => match HighScores::load(&path) {
+            Ok(high_scores) => (high_scores, Some(path)),
+            Err(error) => {
+                eprintln!("Unable to load the high scores from {}: {}", path.display(), error);
+                (HighScores::default(), None)
+            },
+        },
+        None => (HighScores::default(), None),
+    };
+    let mut players = if network.is_some() {
+        vec![Player::new(Some(key_config.single_player.clone()), 0f64, attack_table, handling),
+            Player::new(None, PANEL_WIDTH, attack_table, handling)]
+    } else if versus {
+        vec![Player::new(Some(key_config.player_one.clone()), 0f64, attack_table, handling),
+            Player::new(Some(key_config.player_two.clone()), PANEL_WIDTH, attack_table, handling)]
+    } else {
+        vec![Player::new(Some(key_config.single_player.clone()), 0f64, attack_table, handling)]
+    };
+    for player in players.iter_mut() {
+        player.tetris.set_starting_level(starting_level);
+    }
+    let window_width = WINDOW_WIDTH.max(PANEL_WIDTH * players.len() as f64);
+
+    let mut window: PistonWindow = WindowSettings::new("Piston Tetris", [window_width, WINDOW_HEIGHT]).
         exit_on_esc(true).
         build().
         unwrap();
@@ -268,21 +1647,78 @@ fn start_app() {
     };
 
     let mut app = App {
-        tetris: Tetris::new(),
-        elapsed_time: 0.0,
+        players,
+        winner: None,
+        network,
+        bot_command,
+        weights,
+        perfect_clear_solver: None,
+        opener_trainer: false,
+        hint_planner: None,
+        key_config,
+        key_config_path,
+        rebinding: None,
+        gamepads: Vec::new(),
+        high_scores,
+        high_scores_path,
+        ruleset,
+        name_entries: Vec::new(),
+        show_high_scores: false,
+        high_scores_paused: false,
+        high_score_rank: None,
+        flash_finesse: false,
         glyphs: window.load_font(font_path).unwrap(),
     };  
 
+    // with the gamepad feature, gamepads are also read separately, see handle_gamepad_event()
+    #[cfg(feature = "gamepad")]
+    let mut gilrs = match gilrs::Gilrs::new() {
+        Ok(gilrs) => Some(gilrs),
+        Err(error) => {
+            eprintln!("Unable to read gamepads: {}", error);
+            None
+        },
+    };
+
     window.set_lazy(false);
     while let Some(e) = window.next() {
-        if let Some(Button::Keyboard(key)) = e.press_args() {
-            app.handle_key_input(key);
+        match e.press_args() {
+            Some(Button::Keyboard(key)) => app.handle_key_input(key),
+            Some(Button::Controller(button)) => app.handle_gamepad_button(button, true),
+            Some(Button::Hat(hat)) => app.handle_gamepad_hat(hat, true),
+            _ => {},
         };
 
-        if let Some(args) = e.render_args() {
+        match e.release_args() {
+            Some(Button::Keyboard(key)) => app.handle_key_release(key),
+            Some(Button::Controller(button)) => app.handle_gamepad_button(button, false),
+            Some(Button::Hat(hat)) => app.handle_gamepad_hat(hat, false),
+            _ => {},
+        };
+
+        if let Some(args) = e.controller_axis_args() {
+            app.handle_gamepad_axis(args);
+        };
+
+        #[cfg(feature = "gamepad")]
+        if let Some(ref mut gilrs) = gilrs {
+            while let Some(event) = gilrs.next_event() {
+                app.handle_gamepad_event(event);
+            }
+        };
+
+        if let Some(text) = e.text_args() {
+            app.handle_text(&text);
+        };
+
+        if let Some(focused) = e.focus_args() {
+            app.handle_focus(focused);
+        };
+
+        if e.render_args().is_some() {
             app.render(&mut window, &e);
         };
 
-        e.update(|args| { app.update(&args); });
+        e.update(|args| { app.update(args); });
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,246 @@
+use std::io;
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::mpsc;
+use std::thread;
+
+use rollback::FrameInput;
+
+/// Every connection starts by exchanging a Hello message with these bytes, followed by the
+/// protocol version
+const MAGIC: [u8; 4] = *b"TTRS";
+
+/// Bump the version whenever the layout of a message changes, or the same seed and inputs
+/// no longer play the same game. Both players must use the same version to play each other.
+pub const PROTOCOL_VERSION: u16 = 5;
+
+/// Messages exchanged by two players over the network. Both players simulate both games,
+/// so the only thing sent while playing is each player's input for every frame.
+/// See also RollbackSession.
+///
+/// Each message is written as a single byte tag followed by the fields of the message in
+/// little endian order.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Message {
+    /// The first message sent in either direction, see also PROTOCOL_VERSION
+    Hello { version: u16 },
+    /// Sent by the host to the player joining, with the seed and starting level of the VersusState
+    Start { seed: u64, starting_level: u32 },
+    /// The sender's input for a frame
+    Frame { frame: u32, input: FrameInput },
+}
+
+const HELLO_TAG: u8 = 0;
+const START_TAG: u8 = 1;
+const FRAME_TAG: u8 = 2;
+
+fn invalid_data(text: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, text)
+}
+
+fn read_bytes<R: Read, const N: usize>(reader: &mut R) -> io::Result<[u8; N]> {
+    let mut result = [0u8; N];
+    reader.read_exact(&mut result)?;
+    Ok(result)
+}
+
+impl Message {
+    /// Writes the message, see also Message::read()
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut buffer: Vec<u8> = Vec::new();
+        match *self {
+            Message::Hello { version } => {
+                buffer.push(HELLO_TAG);
+                buffer.extend_from_slice(&MAGIC);
+                buffer.extend_from_slice(&version.to_le_bytes());
+            },
+            Message::Start { seed, starting_level } => {
+                buffer.push(START_TAG);
+                buffer.extend_from_slice(&seed.to_le_bytes());
+                buffer.extend_from_slice(&starting_level.to_le_bytes());
+            },
+            Message::Frame { frame, input } => {
+                buffer.push(FRAME_TAG);
+                buffer.extend_from_slice(&frame.to_le_bytes());
+                buffer.extend_from_slice(&input.bits().to_le_bytes());
+            },
+        }
+        writer.write_all(&buffer)
+    }
+
+    /// Reads a message, blocking until the whole message has arrived
+    pub fn read<R: Read>(reader: &mut R) -> io::Result<Message> {
+        let tag = read_bytes::<R, 1>(reader)?[0];
+        match tag {
+            HELLO_TAG => {
+                if read_bytes::<R, 4>(reader)? != MAGIC {
+                    return Err(invalid_data("Not a tetris-piston connection"));
+                }
+                Ok(Message::Hello { version: u16::from_le_bytes(read_bytes(reader)?) })
+            },
+            START_TAG => Ok(Message::Start {
+                seed: u64::from_le_bytes(read_bytes(reader)?),
+                starting_level: u32::from_le_bytes(read_bytes(reader)?),
+            }),
+            FRAME_TAG => Ok(Message::Frame {
+                frame: u32::from_le_bytes(read_bytes(reader)?),
+                input: FrameInput::from_bits(u16::from_le_bytes(read_bytes(reader)?)),
+            }),
+            _ => Err(invalid_data(&format!("Unknown message {}", tag))),
+        }
+    }
+}
+
+/// A Connection to the opponent. Messages are read on a separate thread, so that
+/// receive() never blocks the game.
+pub struct Connection {
+    stream: TcpStream,
+    incoming: mpsc::Receiver<Message>,
+    connected: bool,
+}
+
+impl Connection {
+    /// Waits for an opponent to join at the given address, e.g. "0.0.0.0:7878"
+    pub fn host(address: &str) -> io::Result<Connection> {
+        Connection::accept(&TcpListener::bind(address)?)
+    }
+
+    /// Waits for an opponent to join on a listener that's already bound, e.g. to port 0 so the
+    /// operating system picks a free port, see also TcpListener::local_addr()
+    pub fn accept(listener: &TcpListener) -> io::Result<Connection> {
+        let (stream, _) = listener.accept()?;
+        Connection::new(stream)
+    }
+
+    /// Joins an opponent hosting at the given address, e.g. "127.0.0.1:7878"
+    pub fn join(address: &str) -> io::Result<Connection> {
+        Connection::new(TcpStream::connect(address)?)
+    }
+
+    fn new(mut stream: TcpStream) -> io::Result<Connection> {
+        // inputs are tiny and must arrive as soon as possible
+        stream.set_nodelay(true)?;
+        Message::Hello { version: PROTOCOL_VERSION }.write(&mut stream)?;
+        match Message::read(&mut stream)? {
+            Message::Hello { version } if version == PROTOCOL_VERSION => {},
+            Message::Hello { version } => {
+                return Err(invalid_data(&format!("Opponent uses protocol version {}, expected {}",
+                    version, PROTOCOL_VERSION)));
+            },
+            _ => return Err(invalid_data("Expected a Hello message")),
+        }
+
+        let (sender, incoming) = mpsc::channel();
+        let mut reader = stream.try_clone()?;
+        thread::spawn(move || {
+            // the thread ends when the connection closes or the Connection is dropped
+            while let Ok(message) = Message::read(&mut reader) {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Connection { stream, incoming, connected: true })
+    }
+
+    /// Sends a message to the opponent. After an error, the connection is no longer connected.
+    pub fn send(&mut self, message: &Message) {
+        if self.connected && message.write(&mut self.stream).is_err() {
+            self.connected = false;
+        }
+    }
+
+    /// Waits for the next message from the opponent. Returns None if the opponent disconnected.
+    pub fn wait_receive(&mut self) -> Option<Message> {
+        match self.incoming.recv() {
+            Ok(message) => Some(message),
+            Err(_) => {
+                self.connected = false;
+                None
+            },
+        }
+    }
+
+    /// Returns the next message received from the opponent, if any
+    pub fn receive(&mut self) -> Option<Message> {
+        match self.incoming.try_recv() {
+            Ok(message) => Some(message),
+            Err(mpsc::TryRecvError::Empty) => None,
+            Err(mpsc::TryRecvError::Disconnected) => {
+                self.connected = false;
+                None
+            },
+        }
+    }
+
+    /// Returns false once the opponent has disconnected
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // also ends the thread reading messages, which holds a clone of the stream
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tetris::Input;
+
+    #[test]
+    fn messages_read_back_as_written() {
+        let mut input = FrameInput::default();
+        input.press(Input::Left);
+        input.press(Input::HardDrop);
+        let messages = [Message::Hello { version: PROTOCOL_VERSION },
+            Message::Start { seed: 0x0123_4567_89ab_cdef, starting_level: 7 },
+            Message::Frame { frame: 1234, input }];
+        let mut buffer: Vec<u8> = Vec::new();
+        for message in messages.iter() {
+            message.write(&mut buffer).unwrap();
+        }
+        let mut reader: &[u8] = &buffer;
+        for message in messages.iter() {
+            assert_eq!(Message::read(&mut reader).unwrap(), *message);
+        }
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn other_connections_are_refused() {
+        let mut reader: &[u8] = &[HELLO_TAG, b'H', b'T', b'T', b'P', 0, 0];
+        assert_eq!(Message::read(&mut reader).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let mut reader: &[u8] = &[9];
+        assert!(Message::read(&mut reader).is_err());
+    }
+
+    #[test]
+    fn players_exchange_messages_over_localhost() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let host = thread::spawn(move || {
+            let mut connection = Connection::accept(&listener).unwrap();
+            connection.send(&Message::Start { seed: 42, starting_level: 3 });
+            let received = connection.wait_receive();
+            (connection, received)
+        });
+        // the listener is bound already, so the host is waiting
+        let mut connection = Connection::join(&address).unwrap();
+        assert_eq!(connection.wait_receive(), Some(Message::Start { seed: 42, starting_level: 3 }));
+        let mut input = FrameInput::default();
+        input.press(Input::RotateClockwise);
+        connection.send(&Message::Frame { frame: 5, input });
+        let (host_connection, received) = host.join().unwrap();
+        assert_eq!(received, Some(Message::Frame { frame: 5, input }));
+        assert!(connection.is_connected());
+
+        // once the host leaves, the player joining finds out
+        drop(host_connection);
+        assert_eq!(connection.wait_receive(), None);
+        assert!(!connection.is_connected());
+    }
+}
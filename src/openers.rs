@@ -0,0 +1,287 @@
+use tetris::*;
+
+/// The letter of each shape in opener patterns, in the order of the SHAPES const
+const SHAPE_LETTERS: [char; SHAPE_COUNT as usize] = ['T', 'O', 'Z', 'S', 'L', 'J', 'I'];
+
+/// An Opener is a setup built at the start of a game, see OPENERS
+#[derive(Debug)]
+pub struct Opener {
+    pub name: &'static str,
+    /// The boards the opener builds, for the different orders its shapes can come in. Each board
+    /// is a list of rows from the top row down to the floor, where each cell is the letter of the
+    /// shape that fills it, or '.' if the cell is empty. Mirror images are recognized too.
+    pub patterns: &'static [&'static [&'static str]],
+}
+
+/// The openers recognized by recognize_openers()
+pub const OPENERS: [Opener; 4] = [
+    // a T-spin double with the first T, the slot covered by a Z standing on a J
+    Opener {
+        name: "TKI-3",
+        patterns: &[
+            &[
+                "..Z...SS..",
+                ".ZZ..SSL..",
+                "JZ...LLLOO",
+                "JJJ.IIIIOO",
+            ],
+            &[
+                ".......S..",
+                "..Z....SS.",
+                ".ZZ....LS.",
+                "JZ...LLLOO",
+                "JJJ.IIIIOO",
+            ],
+        ],
+    },
+    // a T-spin double followed by a T-spin triple, the triple slot under an S hanging off the wall
+    Opener {
+        name: "DT Cannon",
+        patterns: &[
+            &[
+                "...ZZ.....",
+                "S...ZZIIII",
+                "SS.SIIIIOO",
+                "LS.SSJJJOO",
+                "L..JSZZJOO",
+                "LL.JJJZZOO",
+            ],
+        ],
+    },
+    // a T-spin double with the first T, the slot covered by a Z from the right
+    Opener {
+        name: "MKO",
+        patterns: &[
+            &[
+                "S.........",
+                "SS..ZZ.LLL",
+                "JS...ZZLOO",
+                "JJJ.IIIIOO",
+            ],
+        ],
+    },
+    // a perfect clear within 4 rows, using two T's and two shapes of the second bag to fill the gap
+    Opener {
+        name: "PCO",
+        patterns: &[
+            &[
+                "LLLS......",
+                "LZZSS....J",
+                "OOZZS....J",
+                "OOIIII..JJ",
+            ],
+        ],
+    },
+];
+
+/// How far a player has built an opener, see recognize_openers()
+#[derive(Clone, Debug)]
+pub struct OpenerProgress {
+    pub opener: &'static Opener,
+    /// The index into Opener::patterns of the board being built
+    pub pattern: usize,
+    /// True if the player is building the mirror image of the pattern
+    pub mirrored: bool,
+    /// The number of filled cells that match the pattern
+    pub matched: u32,
+    /// The number of cells in the pattern
+    pub total: u32,
+    /// The filled cells that don't match the pattern, where the player deviated from the opener
+    pub deviations: Vec<Point>,
+}
+
+impl OpenerProgress {
+    /// Returns true once every cell of the pattern is filled, without any deviations
+    pub fn is_complete(&self) -> bool {
+        self.matched == self.total && self.deviations.is_empty()
+    }
+
+    /// Returns the letter of the shape the pattern has in a cell, or '.' if the cell should be empty
+    pub fn get_letter(&self, col: i32, row: i32) -> char {
+        let rows = self.opener.patterns[self.pattern];
+        let pattern_row = row - (ROW_COUNT as i32 - rows.len() as i32);
+        if pattern_row < 0 {
+            return '.';
+        }
+        let letters = rows[pattern_row as usize].as_bytes();
+        if self.mirrored {
+            match letters[COL_COUNT as usize - 1 - col as usize] as char {
+                'L' => 'J',
+                'J' => 'L',
+                'S' => 'Z',
+                'Z' => 'S',
+                letter => letter,
+            }
+        } else {
+            letters[col as usize] as char
+        }
+    }
+
+    /// Returns the placement of the current or held shape that builds the next part of the
+    /// pattern, or None if the player has deviated or no shape in play fits the pattern.
+    /// Placements without hold and lower down the board come first.
+    pub fn next_placement(&self, tetris: &Tetris) -> Option<Placement> {
+        if !self.deviations.is_empty() {
+            return None;
+        }
+        tetris.get_placements(true).into_iter()
+            .filter(|placement| placement.get_cells().iter().all(|point| point.y >= 0 &&
+                self.get_letter(point.x as i32, point.y as i32) == SHAPE_LETTERS[placement.shape_index as usize] &&
+                tetris.get_grid_cell(point.x as i32, point.y as i32).cell_type != GridCellType::Fixed))
+            .max_by_key(|placement| (placement.path.first() != Some(&Input::Hold),
+                placement.get_cells().iter().map(|point| point.y as i32).sum::<i32>()))
+    }
+}
+
+/// Returns the openers the player could be building, best first: those matching the most
+/// filled cells, then with the fewest deviations. Every opener is returned while the board is
+/// empty, and none once the board matches no opener at all.
+pub fn recognize_openers(tetris: &Tetris) -> Vec<OpenerProgress> {
+    let mut result: Vec<OpenerProgress> = Vec::new();
+    for opener in OPENERS.iter() {
+        for (pattern, rows) in opener.patterns.iter().enumerate() {
+            for mirrored in [false, true] {
+                let mut progress = OpenerProgress {
+                    opener,
+                    pattern,
+                    mirrored,
+                    matched: 0,
+                    total: rows.iter().map(|row| row.chars().filter(|letter| *letter != '.').count() as u32).sum(),
+                    deviations: Vec::new(),
+                };
+                for row in 0..ROW_COUNT as i32 {
+                    for col in 0..COL_COUNT as i32 {
+                        let cell = tetris.get_grid_cell(col, row);
+                        if cell.cell_type != GridCellType::Fixed {
+                            continue;
+                        }
+                        let letter = SHAPE_LETTERS.get(cell.shape_index as usize).cloned();
+                        if letter == Some(progress.get_letter(col, row)) {
+                            progress.matched += 1;
+                        } else {
+                            progress.deviations.push(Point { x: col as i16, y: row as i16 });
+                        }
+                    }
+                }
+                if progress.matched > 0 || progress.deviations.is_empty() {
+                    result.push(progress);
+                }
+            }
+        }
+    }
+    // a stable sort, so openers keep the order of OPENERS when equally matched
+    result.sort_by(|a, b| b.matched.cmp(&a.matched).then(a.deviations.len().cmp(&b.deviations.len())));
+    result
+}
+
+/// Returns the opener the player is most likely building, see recognize_openers()
+pub fn recognize_opener(tetris: &Tetris) -> Option<OpenerProgress> {
+    recognize_openers(tetris).into_iter().next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const T: i32 = T_SHAPE_INDEX;
+    const O: i32 = SQUARE_SHAPE_INDEX;
+    const Z: i32 = 2;
+    const S: i32 = 3;
+    const L: i32 = 4;
+    const J: i32 = 5;
+    const I: i32 = 6;
+
+    /// Returns the progress of the first pattern of the named opener, as it's drawn in OPENERS
+    fn progress(tetris: &Tetris, name: &str) -> OpenerProgress {
+        recognize_openers(tetris).into_iter()
+            .find(|progress| progress.opener.name == name && progress.pattern == 0 && !progress.mirrored)
+            .unwrap()
+    }
+
+    /// Plays the placements suggested for an opener, with the shapes coming in the given order
+    fn build(name: &str, shape_indices: &[i32]) -> Tetris {
+        let mut tetris = Tetris::with_board(&[], -1, shape_indices);
+        for (index, shape_index) in shape_indices.iter().enumerate() {
+            let progress = progress(&tetris, name);
+            assert_eq!(progress.matched, index as u32 * POINT_COUNT as u32);
+            assert!(progress.deviations.is_empty());
+            let placement = progress.next_placement(&tetris).unwrap();
+            assert_eq!(placement.shape_index, *shape_index);
+            assert_ne!(placement.path.first(), Some(&Input::Hold));
+            for input in placement.path.iter() {
+                tetris.apply_input(*input);
+            }
+        }
+        let best = recognize_opener(&tetris).unwrap();
+        assert_eq!(best.opener.name, name);
+        assert!(best.is_complete());
+        tetris
+    }
+
+    #[test]
+    fn every_opener_is_recognized_on_an_empty_board() {
+        let tetris = Tetris::with_board(&[], -1, &[I]);
+        let openers = recognize_openers(&tetris);
+        // each pattern and its mirror image
+        assert_eq!(openers.len(), 2 * OPENERS.iter().map(|opener| opener.patterns.len()).sum::<usize>());
+        assert!(openers.iter().all(|progress| progress.matched == 0 && progress.deviations.is_empty()));
+        assert_eq!(openers[0].opener.name, "TKI-3");
+    }
+
+    #[test]
+    fn tki_3_is_built() {
+        build("TKI-3", &[I, J, O, L, S, Z]);
+    }
+
+    #[test]
+    fn dt_cannon_is_built() {
+        build("DT Cannon", &[L, J, Z, O, O, J, S, S, I, I, Z]);
+    }
+
+    #[test]
+    fn mko_is_built() {
+        build("MKO", &[J, I, O, S, Z, L]);
+    }
+
+    #[test]
+    fn pco_is_built() {
+        build("PCO", &[O, I, J, Z, S, L]);
+    }
+
+    #[test]
+    fn the_next_placement_fills_the_pattern() {
+        let tetris = Tetris::with_board(&[], -1, &[I]);
+        let placement = progress(&tetris, "TKI-3").next_placement(&tetris).unwrap();
+        let mut cells: Vec<(i16, i16)> = placement.get_cells().iter().map(|point| (point.x, point.y)).collect();
+        cells.sort();
+        assert_eq!(cells, vec![(4, 21), (5, 21), (6, 21), (7, 21)]);
+
+        // the T isn't part of the pattern, so the I coming next is held in
+        let tetris = Tetris::with_board(&[], -1, &[T, I]);
+        let placement = progress(&tetris, "TKI-3").next_placement(&tetris).unwrap();
+        assert_eq!(placement.path.first(), Some(&Input::Hold));
+        assert_eq!(placement.shape_index, I);
+    }
+
+    #[test]
+    fn deviations_are_reported() {
+        // the I of TKI-3, then an O dropped on it where the L goes
+        let mut tetris = Tetris::with_board(&[], -1, &[I, O]);
+        let placement = progress(&tetris, "TKI-3").next_placement(&tetris).unwrap();
+        for input in placement.path.iter() {
+            tetris.apply_input(*input);
+        }
+        tetris.hard_drop();
+        let progress = progress(&tetris, "TKI-3");
+        assert_eq!(progress.matched, 4);
+        assert_eq!(progress.deviations.len(), 4);
+        assert!(progress.deviations.contains(&Point { x: 4, y: 20 }));
+        assert!(progress.next_placement(&tetris).is_none());
+        assert_eq!(recognize_opener(&tetris).unwrap().opener.name, "TKI-3");
+
+        // the board matches no opener at all once every filled cell deviates
+        let mut tetris = Tetris::with_board(&[], -1, &[O]);
+        tetris.hard_drop();
+        assert!(recognize_opener(&tetris).is_none());
+    }
+}
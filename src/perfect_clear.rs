@@ -0,0 +1,272 @@
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use tetris::*;
+
+/// The number of rows a perfect clear is usually built within
+pub const PERFECT_CLEAR_ROWS: u32 = 4;
+
+/// A PerfectClearSolver searches for a sequence of placements that empties the board without
+/// building higher than a number of rows. It only plays the shapes the player can see: the
+/// current shape, the held shape and the preview queue, so a perfect clear needing more shapes
+/// isn't found until they come into view. From an empty board, that's a perfect clear of 2 rows.
+/// The solver can be cloned to search on another thread.
+#[derive(Clone)]
+pub struct PerfectClearSolver {
+    /// The height from the floor that the placements must stay within
+    max_rows: u32,
+    /// The search gives up once this much time has passed
+    time_budget: Duration,
+}
+
+/// The state of a search, see PerfectClearSolver::search()
+struct Search {
+    start: Instant,
+    /// The games already searched, by their rows, held shape and shapes played
+    visited: HashSet<([u16; ROW_COUNT as usize], i32, u32)>,
+    /// The placements leading to the game being searched
+    path: Vec<Placement>,
+}
+
+impl PerfectClearSolver {
+    pub fn new(max_rows: u32, time_budget: Duration) -> PerfectClearSolver {
+        PerfectClearSolver {
+            max_rows: max_rows.min(ROW_COUNT as u32),
+            time_budget,
+        }
+    }
+
+    pub fn get_max_rows(&self) -> u32 {
+        self.max_rows
+    }
+
+    pub fn get_time_budget(&self) -> Duration {
+        self.time_budget
+    }
+
+    /// Returns the placements, in order, that empty the board, or None if no perfect clear can be
+    /// made with the shapes in view or none was found within the time budget. Each placement's
+    /// path is played from the game left by the placements before it.
+    pub fn solve(&self, tetris: &Tetris) -> Option<Vec<Placement>> {
+        let rows = tetris.get_row_bits();
+        if tetris.get_game_over() || rows[..(ROW_COUNT as u32 - self.max_rows) as usize].iter().any(|bits| *bits != 0) {
+            return None;
+        }
+        let start = Instant::now();
+        // try the lowest perfect clears first, since they need the fewest shapes. An empty board
+        // needs at least one row filled and cleared.
+        let height = tetris.analyze_board().column_heights().iter().cloned().max().unwrap_or(0);
+        for rows_left in height.max(1)..=self.max_rows {
+            if !Self::can_fill(rows, rows_left, Self::shapes_left(tetris, 0)) {
+                continue;
+            }
+            let mut search = Search {
+                start,
+                visited: HashSet::new(),
+                path: Vec::new(),
+            };
+            if self.search(&mut search, tetris, rows_left, 0) {
+                return Some(search.path);
+            }
+        }
+        None
+    }
+
+    /// Tries every placement of the current and held shapes that fills the rows left, without
+    /// building above them, returning true with the placements in search.path once the board is empty.
+    /// played is the number of shapes taken from the current shape and queue so far, whether they
+    /// were fixed into place or swapped into hold.
+    fn search(&self, search: &mut Search, tetris: &Tetris, rows_left: u32, played: u32) -> bool {
+        if played > 0 && tetris.get_row_bits().iter().all(|bits| *bits == 0) {
+            return true;
+        }
+        if search.start.elapsed() > self.time_budget {
+            return false;
+        }
+        let top = (ROW_COUNT as u32 - rows_left) as i16;
+        let shown = 1 + PREVIEW_COUNT as u32;
+        for placement in tetris.get_placements(true) {
+            let holding = placement.path.first() == Some(&Input::Hold);
+            let (played, in_view) = if !holding {
+                (played + 1, played < shown)
+            } else if tetris.get_hold_shape_index() < 0 {
+                // holding for the first time plays the next shape from the queue
+                (played + 2, played + 1 < shown)
+            } else {
+                // the held shape is in view, unless it was swapped in after the queue ran out
+                (played + 1, played <= shown)
+            };
+            if !in_view || placement.get_cells().iter().any(|point| point.y < top) {
+                continue;
+            }
+            let mut next = tetris.clone();
+            let mut rows = 0;
+            for input in placement.path.iter() {
+                if let Some(result) = next.apply_input(*input) {
+                    rows = result.rows as u32;
+                }
+            }
+            let rows_left = rows_left - rows;
+            if next.get_game_over() ||
+                !Self::can_fill(next.get_row_bits(), rows_left, Self::shapes_left(&next, played)) ||
+                !search.visited.insert((*next.get_row_bits(), next.get_hold_shape_index(), played)) {
+                continue;
+            }
+            search.path.push(placement);
+            if self.search(search, &next, rows_left, played) {
+                return true;
+            }
+            search.path.pop();
+        }
+        false
+    }
+
+    /// Returns the most shapes in view that can still be fixed into place: those left of the current
+    /// shape and queue, and the held shape unless it was swapped in after the queue ran out
+    fn shapes_left(tetris: &Tetris, played: u32) -> u32 {
+        let shown = 1 + PREVIEW_COUNT as u32;
+        let held = tetris.get_hold_shape_index() >= 0 && played <= shown;
+        shown.saturating_sub(played) + held as u32
+    }
+
+    /// Returns false if the empty cells of the bottom rows can't all be filled by the shapes left,
+    /// including when they split into groups that aren't a multiple of 4 cells
+    fn can_fill(rows: &[u16; ROW_COUNT as usize], rows_left: u32, shapes_left: u32) -> bool {
+        let top = (ROW_COUNT as u32 - rows_left) as usize;
+        if rows[..top].iter().any(|bits| *bits != 0) {
+            return false;
+        }
+        let mut empty = [0u16; ROW_COUNT as usize];
+        for row in top..ROW_COUNT as usize {
+            empty[row] = !rows[row] & FULL_ROW;
+        }
+        let total: u32 = empty.iter().map(|bits| bits.count_ones()).sum();
+        if total > shapes_left * POINT_COUNT as u32 {
+            return false;
+        }
+        // flood fill each group of empty cells in turn
+        while let Some(row) = (top..ROW_COUNT as usize).find(|row| empty[*row] != 0) {
+            let mut group = [0u16; ROW_COUNT as usize];
+            group[row] = empty[row] & empty[row].wrapping_neg();
+            let mut changed = true;
+            while changed {
+                changed = false;
+                for row in top..ROW_COUNT as usize {
+                    let mut bits = group[row] | (group[row] << 1) | (group[row] >> 1);
+                    if row > top {
+                        bits |= group[row - 1];
+                    }
+                    if row + 1 < ROW_COUNT as usize {
+                        bits |= group[row + 1];
+                    }
+                    bits &= empty[row];
+                    if bits != group[row] {
+                        group[row] = bits;
+                        changed = true;
+                    }
+                }
+            }
+            let size: u32 = group.iter().map(|bits| bits.count_ones()).sum();
+            if !size.is_multiple_of(POINT_COUNT as u32) {
+                return false;
+            }
+            for row in top..ROW_COUNT as usize {
+                empty[row] &= !group[row];
+            }
+        }
+        true
+    }
+}
+
+impl Default for PerfectClearSolver {
+    fn default() -> PerfectClearSolver {
+        PerfectClearSolver::new(PERFECT_CLEAR_ROWS, Duration::from_millis(100))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const O: i32 = SQUARE_SHAPE_INDEX;
+    const S: i32 = 3;
+    const L: i32 = 4;
+    const J: i32 = 5;
+    const I: i32 = 6;
+
+    fn solver() -> PerfectClearSolver {
+        PerfectClearSolver::new(PERFECT_CLEAR_ROWS, Duration::from_secs(10))
+    }
+
+    /// Plays the placements of a solution in turn, returning the game left
+    fn play(tetris: &Tetris, placements: &[Placement]) -> Tetris {
+        let mut tetris = tetris.clone();
+        for placement in placements {
+            let top = (ROW_COUNT as u32 - PERFECT_CLEAR_ROWS) as i16;
+            assert!(placement.get_cells().iter().all(|point| point.y >= top));
+            for input in placement.path.iter() {
+                tetris.apply_input(*input);
+            }
+        }
+        tetris
+    }
+
+    #[test]
+    fn two_rows_are_cleared_from_an_empty_board() {
+        let tetris = Tetris::with_board(&[], -1, &[O, O, O, O, O, O]);
+        let placements = solver().solve(&tetris).unwrap();
+        assert_eq!(placements.len(), 5);
+        let tetris = play(&tetris, &placements);
+        assert!(tetris.get_row_bits().iter().all(|bits| *bits == 0));
+        assert_eq!(tetris.get_rows_completed(), 2);
+    }
+
+    #[test]
+    fn four_rows_are_cleared_after_an_opener() {
+        // four rows left to fill by a J under an L and two I's
+        let tetris = Tetris::with_board(&[
+            "XXX.......",
+            "XXX......X",
+            "XXXX...XXX",
+            "XXXXXXXXXX",
+        ], -1, &[J, L, I, I, O, O]);
+        let placements = solver().solve(&tetris).unwrap();
+        assert_eq!(placements.len(), 4);
+        let tetris = play(&tetris, &placements);
+        assert!(tetris.get_row_bits().iter().all(|bits| *bits == 0));
+        assert_eq!(tetris.get_rows_completed(), 4);
+    }
+
+    #[test]
+    fn no_perfect_clear_with_the_shapes_in_view() {
+        // the O's can fill the corner, but not a single row across the rest of the board
+        let tetris = Tetris::with_board(&["XXXXXXXX.."], -1, &[O, O, O, O, O, O]);
+        assert!(solver().solve(&tetris).is_none());
+    }
+
+    #[test]
+    fn the_current_shape_is_held_when_it_doesnt_fit() {
+        let tetris = Tetris::with_board(&[], -1, &[S, O, O, O, O, O]);
+        let placements = solver().solve(&tetris).unwrap();
+        assert_eq!(placements[0].path.first(), Some(&Input::Hold));
+        assert_eq!(placements.len(), 5);
+        let tetris = play(&tetris, &placements);
+        assert!(tetris.get_row_bits().iter().all(|bits| *bits == 0));
+    }
+
+    #[test]
+    fn the_held_shape_is_played_after_the_queue() {
+        // seven O's are needed, the last of them the held one
+        let tetris = Tetris::with_board(&[
+            "XX........",
+            "XX........",
+            "XXXX......",
+            "XXXX......",
+        ], O, &[O, O, O, O, O, O]);
+        let placements = solver().solve(&tetris).unwrap();
+        assert_eq!(placements.len(), 7);
+        assert_eq!(placements[6].path.first(), Some(&Input::Hold));
+        let tetris = play(&tetris, &placements);
+        assert!(tetris.get_row_bits().iter().all(|bits| *bits == 0));
+    }
+}
@@ -0,0 +1,394 @@
+use std::collections::VecDeque;
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use tetris::*;
+
+/// The number of frames simulated each second
+pub const FRAMES_PER_SECOND: u32 = 60;
+
+/// The furthest the local player may get ahead of the last frame with known inputs
+/// from the opponent, before waiting for the opponent to catch up
+pub const MAX_PREDICTION_FRAMES: u32 = 30;
+
+/// The order inputs are applied to a game when several are pressed in the same frame
+const INPUT_ORDER: [Input; 8] = [Input::Hold, Input::RotateClockwise, Input::RotateCounterClockwise,
+    Input::Rotate180, Input::Left, Input::Right, Input::SoftDrop, Input::HardDrop];
+
+/// Set when the player asks for a new game, see also VersusState::step()
+const NEW_GAME_BIT: u16 = 1 << 15;
+
+/// The inputs pressed by one player during a single frame, stored as bit flags
+#[derive(Copy, Clone, PartialEq, Default, Debug)]
+pub struct FrameInput {
+    bits: u16,
+}
+
+impl FrameInput {
+    pub fn from_bits(bits: u16) -> FrameInput {
+        FrameInput { bits }
+    }
+
+    pub fn bits(self) -> u16 {
+        self.bits
+    }
+
+    /// Adds an input pressed during the frame. Gravity is part of the simulation,
+    /// so Input::Tick is ignored.
+    pub fn press(&mut self, input: Input) {
+        if let Some(index) = INPUT_ORDER.iter().position(|other| *other == input) {
+            self.bits |= 1 << index;
+        }
+    }
+
+    pub fn press_new_game(&mut self) {
+        self.bits |= NEW_GAME_BIT;
+    }
+
+    pub fn get_new_game(self) -> bool {
+        self.bits & NEW_GAME_BIT != 0
+    }
+
+    /// Returns the inputs pressed during the frame, in the order they are applied
+    pub fn inputs(self) -> Vec<Input> {
+        INPUT_ORDER.iter().enumerate()
+            .filter(|&(index, _)| self.bits & (1 << index) != 0)
+            .map(|(_, input)| *input)
+            .collect()
+    }
+}
+
+/// The state of a two player versus game, advanced one frame at a time. Given the same seed
+/// and the same inputs, every copy of the state plays exactly the same game, so both players
+/// can simulate both games. Cloning the state takes a snapshot to roll back to.
+#[derive(Clone)]
+pub struct VersusState {
+    games: [Tetris; 2],
+    /// The frames since each game last advanced a row, see also Tetris::get_tick_time()
+    frames_since_tick: [u32; 2],
+    /// The index of the last player left standing
+    winner: Option<usize>,
+    /// Determines the seed of each new game and the hole in garbage rows
+    rng: StdRng,
+}
+
+impl VersusState {
+    pub fn new(seed: u64, starting_level: u32, attack_table: &AttackTable) -> VersusState {
+        let mut games = [Tetris::new(), Tetris::new()];
+        for tetris in games.iter_mut() {
+            tetris.set_starting_level(starting_level);
+            tetris.set_attack_table(attack_table.clone());
+        }
+        VersusState {
+            games,
+            frames_since_tick: [0, 0],
+            winner: None,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn get_games(&self) -> &[Tetris; 2] {
+        &self.games
+    }
+
+    pub fn get_winner(&self) -> Option<usize> {
+        self.winner
+    }
+
+    /// Advances both games by one frame
+    pub fn step(&mut self, inputs: [FrameInput; 2]) {
+        // either player can start a new game once both games are over
+        if inputs.iter().any(|input| input.get_new_game()) && self.games.iter().all(|tetris| tetris.get_game_over()) {
+            for index in 0..self.games.len() {
+                let seed = self.rng.gen();
+                self.games[index].set_seed(seed);
+                self.games[index].start_game();
+                self.frames_since_tick[index] = 0;
+            }
+            self.winner = None;
+        }
+
+        for (index, frame_input) in inputs.iter().enumerate() {
+            for input in frame_input.inputs() {
+                if input == Input::HardDrop {
+                    self.frames_since_tick[index] = 0;
+                }
+                let result = self.games[index].apply_input(input);
+                self.send_garbage(index, result);
+            }
+            if !self.games[index].get_game_over() {
+                self.frames_since_tick[index] += 1;
+                let tick_frames = self.games[index].get_tick_time() * FRAMES_PER_SECOND as f32;
+                if self.frames_since_tick[index] as f32 > tick_frames {
+                    self.frames_since_tick[index] = 0;
+                    let result = self.games[index].tick();
+                    self.send_garbage(index, result);
+                }
+            }
+        }
+
+        // the last player standing wins and the other game ends
+        if self.winner.is_none() {
+            let mut playing = (0..self.games.len()).filter(|index| !self.games[*index].get_game_over());
+            if let (Some(index), None) = (playing.next(), playing.next()) {
+                self.winner = Some(index);
+                self.games[index].end_game();
+            }
+        }
+    }
+
+    /// Sends the attack of a fixed shape to the opponent, after cancelling the player's own garbage
+    fn send_garbage(&mut self, from_index: usize, result: Option<LockResult>) {
+        if let Some(result) = result {
+            let rows = self.games[from_index].cancel_garbage(result.attack);
+            if rows > 0 {
+                let hole_col = self.rng.gen_range(0, COL_COUNT as i32);
+                self.games[1 - from_index].queue_garbage(rows, hole_col);
+            }
+        }
+    }
+}
+
+/// A RollbackSession runs a VersusState for one of the two players of a network game.
+/// The local player's inputs are simulated immediately, predicting the opponent pressed
+/// nothing. When the opponent's inputs arrive and differ from the prediction, the state
+/// is rolled back to the last frame where both players' inputs were known, and
+/// simulated again up to the current frame.
+pub struct RollbackSession {
+    /// The index of the local player in the VersusState
+    local_index: usize,
+    /// The number of frames simulated, see also state
+    frame: u32,
+    /// The state after simulating every frame, including predictions
+    state: VersusState,
+    /// The number of frames simulated with the inputs of both players known
+    confirmed_frame: u32,
+    /// The state after simulating confirmed_frame frames, without any predictions
+    confirmed: VersusState,
+    /// The local inputs from confirmed_frame onwards
+    local_inputs: VecDeque<FrameInput>,
+    /// The opponent's inputs from confirmed_frame onwards
+    remote_inputs: VecDeque<FrameInput>,
+}
+
+impl RollbackSession {
+    pub fn new(local_index: usize, state: VersusState) -> RollbackSession {
+        assert!(local_index < 2);
+        RollbackSession {
+            local_index,
+            frame: 0,
+            confirmed: state.clone(),
+            state,
+            confirmed_frame: 0,
+            local_inputs: VecDeque::new(),
+            remote_inputs: VecDeque::new(),
+        }
+    }
+
+    /// Returns the state of both games as of the current frame
+    pub fn get_state(&self) -> &VersusState {
+        &self.state
+    }
+
+    pub fn get_local_index(&self) -> usize {
+        self.local_index
+    }
+
+    /// Returns false while the local player is too far ahead of the opponent's inputs
+    pub fn can_advance(&self) -> bool {
+        self.frame - self.confirmed_frame < MAX_PREDICTION_FRAMES
+    }
+
+    /// Simulates the next frame with the local player's input, predicting the opponent's
+    /// input if it hasn't arrived yet. Returns the frame number of the input, to send to the opponent.
+    pub fn advance(&mut self, input: FrameInput) -> u32 {
+        let result = self.frame;
+        self.local_inputs.push_back(input);
+        let offset = (self.frame - self.confirmed_frame) as usize;
+        let remote_input = self.remote_inputs.get(offset).cloned().unwrap_or_default();
+        self.state.step(self.ordered(input, remote_input));
+        self.frame += 1;
+        self.confirm();
+        result
+    }
+
+    /// Adds the opponent's input for a frame. Inputs must be added in frame order.
+    pub fn add_remote_input(&mut self, frame: u32, input: FrameInput) {
+        if frame != self.confirmed_frame + self.remote_inputs.len() as u32 {
+            return;
+        }
+        self.remote_inputs.push_back(input);
+        // the frame was already simulated with a prediction of no input
+        let rollback = frame < self.frame && input != FrameInput::default();
+        self.confirm();
+        if rollback {
+            self.state = self.confirmed.clone();
+            for offset in 0..self.local_inputs.len() {
+                let remote_input = self.remote_inputs.get(offset).cloned().unwrap_or_default();
+                self.state.step(self.ordered(self.local_inputs[offset], remote_input));
+            }
+        }
+    }
+
+    /// Advances the confirmed state for every frame where the inputs of both players are known
+    fn confirm(&mut self) {
+        while !self.local_inputs.is_empty() && !self.remote_inputs.is_empty() {
+            let local_input = self.local_inputs.pop_front().unwrap();
+            let remote_input = self.remote_inputs.pop_front().unwrap();
+            let inputs = self.ordered(local_input, remote_input);
+            self.confirmed.step(inputs);
+            self.confirmed_frame += 1;
+        }
+    }
+
+    fn ordered(&self, local_input: FrameInput, remote_input: FrameInput) -> [FrameInput; 2] {
+        if self.local_index == 0 {
+            [local_input, remote_input]
+        } else {
+            [remote_input, local_input]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Everything about a VersusState the players can see, to compare two states
+    fn fingerprint(state: &VersusState) -> String {
+        let games: Vec<String> = state.get_games().iter()
+            .map(|tetris| format!("{:?}", (tetris.get_row_bits(), tetris.get_score(), tetris.get_rows_completed(),
+                tetris.get_shape_count(), tetris.get_shape_index(), tetris.get_col(), tetris.get_row(),
+                tetris.get_rotation(), tetris.get_hold_shape_index(), tetris.get_next_queue(),
+                tetris.get_pending_garbage(), tetris.get_game_over())))
+            .collect();
+        format!("{:?} {:?}", games, state.get_winner())
+    }
+
+    /// Random inputs for both players, starting a game on the first frame
+    fn random_inputs(seed: u64, frames: usize) -> Vec<[FrameInput; 2]> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut result = Vec::new();
+        for frame in 0..frames {
+            let mut inputs = [FrameInput::default(); 2];
+            for input in inputs.iter_mut() {
+                if frame == 0 {
+                    input.press_new_game();
+                } else if rng.gen_range(0, 4) == 0 {
+                    input.press(INPUT_ORDER[rng.gen_range(0, INPUT_ORDER.len())]);
+                }
+            }
+            result.push(inputs);
+        }
+        result
+    }
+
+    fn new_state() -> VersusState {
+        VersusState::new(7, 5, &AttackTable::default())
+    }
+
+    /// Plays the inputs in a RollbackSession, with each remote input arriving delay frames late
+    fn play_session(local_index: usize, inputs: &[[FrameInput; 2]], delay: usize) -> RollbackSession {
+        let mut session = RollbackSession::new(local_index, new_state());
+        let mut sent = 0;
+        for frame in 0..inputs.len() {
+            while sent + delay <= frame {
+                session.add_remote_input(sent as u32, inputs[sent][1 - local_index]);
+                sent += 1;
+            }
+            assert!(session.can_advance());
+            assert_eq!(session.advance(inputs[frame][local_index]), frame as u32);
+        }
+        while sent < inputs.len() {
+            session.add_remote_input(sent as u32, inputs[sent][1 - local_index]);
+            sent += 1;
+        }
+        session
+    }
+
+    #[test]
+    fn frame_input_keeps_its_inputs() {
+        let mut input = FrameInput::default();
+        input.press(Input::HardDrop);
+        input.press(Input::Hold);
+        input.press(Input::Tick);
+        assert_eq!(input.inputs(), vec![Input::Hold, Input::HardDrop]);
+        assert!(!input.get_new_game());
+        input.press_new_game();
+        assert_eq!(FrameInput::from_bits(input.bits()), input);
+        assert!(FrameInput::from_bits(input.bits()).get_new_game());
+    }
+
+    #[test]
+    fn same_seed_and_inputs_play_the_same_game() {
+        let inputs = random_inputs(1, 2000);
+        let mut one = new_state();
+        let mut two = new_state();
+        for frame_inputs in inputs.iter() {
+            one.step(*frame_inputs);
+            two.step(*frame_inputs);
+        }
+        assert!(one.get_games()[0].get_shape_count() > 10);
+        assert_eq!(fingerprint(&one), fingerprint(&two));
+    }
+
+    #[test]
+    fn late_remote_inputs_roll_back_to_the_same_state() {
+        let inputs = random_inputs(2, 2000);
+        let mut expected = new_state();
+        for frame_inputs in inputs.iter() {
+            expected.step(*frame_inputs);
+        }
+        for local_index in 0..2 {
+            for &delay in [0, 1, 7, MAX_PREDICTION_FRAMES as usize - 1].iter() {
+                let session = play_session(local_index, &inputs, delay);
+                assert_eq!(fingerprint(session.get_state()), fingerprint(&expected),
+                    "local player {} with remote inputs {} frames late", local_index, delay);
+            }
+        }
+    }
+
+    #[test]
+    fn late_remote_input_replaces_the_prediction() {
+        let mut inputs = random_inputs(3, 100);
+        for frame_inputs in inputs.iter_mut().skip(1) {
+            frame_inputs[1] = FrameInput::default();
+        }
+        // the opponent's hard drop arrives after the local player has gone on without it
+        inputs[20][1].press(Input::HardDrop);
+        let mut session = RollbackSession::new(0, new_state());
+        for (frame, frame_inputs) in inputs.iter().enumerate() {
+            if frame < 20 {
+                session.add_remote_input(frame as u32, frame_inputs[1]);
+            }
+            session.advance(frame_inputs[0]);
+        }
+        let predicted = session.get_state().get_games()[1].get_shape_count();
+        for (frame, frame_inputs) in inputs.iter().enumerate().skip(20) {
+            session.add_remote_input(frame as u32, frame_inputs[1]);
+        }
+        assert_eq!(session.get_state().get_games()[1].get_shape_count(), predicted + 1);
+
+        let mut expected = new_state();
+        for frame_inputs in inputs.iter() {
+            expected.step(*frame_inputs);
+        }
+        assert_eq!(fingerprint(session.get_state()), fingerprint(&expected));
+    }
+
+    #[test]
+    fn prediction_stops_at_the_limit() {
+        let mut session = RollbackSession::new(1, new_state());
+        for _ in 0..MAX_PREDICTION_FRAMES {
+            assert!(session.can_advance());
+            session.advance(FrameInput::default());
+        }
+        assert!(!session.can_advance());
+        // inputs out of order are ignored
+        session.add_remote_input(1, FrameInput::default());
+        assert!(!session.can_advance());
+        session.add_remote_input(0, FrameInput::default());
+        assert!(session.can_advance());
+    }
+}
@@ -0,0 +1,213 @@
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+
+use ai::Planner;
+use tetris::*;
+
+/// The number of rows to complete in a sprint
+pub const SPRINT_ROWS: u32 = 40;
+
+/// How a simulated game ends, besides the bot topping out
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Mode {
+    /// Play until the shape limit is reached
+    Marathon,
+    /// Play until SPRINT_ROWS rows are completed, or the shape limit is reached
+    Sprint,
+}
+
+impl Mode {
+    /// Returns the mode for a name given on the command line, "marathon" or "sprint"
+    pub fn from_name(name: &str) -> Option<Mode> {
+        match name {
+            "marathon" => Some(Mode::Marathon),
+            "sprint" => Some(Mode::Sprint),
+            _ => None,
+        }
+    }
+}
+
+/// The result of a single simulated game
+#[derive(Serialize, Clone, Debug)]
+pub struct GameStats {
+    pub seed: u64,
+    pub rows: u32,
+    pub score: u32,
+    pub attack: u32,
+    /// The number of shapes fixed into place
+    pub pieces: u32,
+    /// True if the game ended because the bot topped out
+    pub topped_out: bool,
+}
+
+/// The average results of many simulated games
+#[derive(Serialize, Clone, Debug)]
+pub struct Summary {
+    pub games: u32,
+    pub rows: f64,
+    pub score: f64,
+    pub attack: f64,
+    pub pieces: f64,
+    /// The fraction of games that ended with the bot topping out, 0 to 1
+    pub top_out_rate: f64,
+}
+
+impl Summary {
+    pub fn from_games(games: &[GameStats]) -> Summary {
+        let count = games.len().max(1) as f64;
+        let mean = |value: &dyn Fn(&GameStats) -> f64| games.iter().map(value).sum::<f64>() / count;
+        Summary {
+            games: games.len() as u32,
+            rows: mean(&|game| game.rows as f64),
+            score: mean(&|game| game.score as f64),
+            attack: mean(&|game| game.attack as f64),
+            pieces: mean(&|game| game.pieces as f64),
+            top_out_rate: mean(&|game| if game.topped_out { 1.0 } else { 0.0 }),
+        }
+    }
+
+    /// Returns the column names of the CSV format, see also to_csv()
+    pub fn csv_header() -> &'static str {
+        "games,rows,score,attack,pieces,top_out_rate"
+    }
+
+    pub fn to_csv(&self) -> String {
+        format!("{},{},{},{},{},{}", self.games, self.rows, self.score, self.attack, self.pieces, self.top_out_rate)
+    }
+}
+
+/// A Simulation plays games without a window or timers, as fast as the bot can choose placements.
+/// Each placement is played out in full as soon as it's chosen, so gravity never moves a shape.
+pub struct Simulation {
+    pub mode: Mode,
+    pub attack_table: AttackTable,
+    pub starting_level: u32,
+    /// The number of shapes after which a game ends
+    pub max_pieces: u32,
+}
+
+impl Default for Simulation {
+    fn default() -> Simulation {
+        Simulation {
+            mode: Mode::Marathon,
+            attack_table: AttackTable::guideline(),
+            starting_level: 0,
+            max_pieces: 1000,
+        }
+    }
+}
+
+impl Simulation {
+    /// Plays a single game, seeded so the same seed and bot always play the same game
+    pub fn play(&self, planner: &mut dyn Planner, seed: u64) -> GameStats {
+        let mut tetris = Tetris::new();
+        tetris.set_attack_table(self.attack_table.clone());
+        tetris.set_starting_level(self.starting_level);
+        tetris.set_seed(seed);
+        tetris.start_game();
+
+        while !tetris.get_game_over() && !self.is_finished(&tetris) {
+            match planner.choose(&tetris) {
+                Some(placement) => {
+                    for input in placement.path {
+                        tetris.apply_input(input);
+                    }
+                },
+                // a bot without a placement lets the shape drop where it is
+                None => {
+                    tetris.hard_drop();
+                },
+            }
+        }
+
+        GameStats {
+            seed,
+            rows: tetris.get_rows_completed(),
+            score: tetris.get_score(),
+            attack: tetris.get_attack(),
+            pieces: tetris.get_shape_count(),
+            topped_out: tetris.get_game_over(),
+        }
+    }
+
+    /// Plays a game for each seed in the range, one after another
+    pub fn play_games(&self, planner: &mut dyn Planner, seeds: Range<u64>) -> Vec<GameStats> {
+        seeds.map(|seed| self.play(planner, seed)).collect()
+    }
+
+    /// Plays a game for each seed in the range, on several threads at once. Each thread plays
+    /// with its own planner from new_planner, and the results are in the order of the seeds,
+    /// the same as play_games() with the same planner.
+    pub fn play_games_parallel<F>(&self, new_planner: F, seeds: Range<u64>, threads: usize) -> Vec<GameStats>
+        where F: Fn() -> Box<dyn Planner> + Sync {
+        let next_seed = AtomicU64::new(seeds.start);
+        let mut results: Vec<GameStats> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..threads.max(1)).map(|_| scope.spawn(|| {
+                let mut planner = new_planner();
+                let mut results = Vec::new();
+                loop {
+                    let seed = next_seed.fetch_add(1, Ordering::Relaxed);
+                    if seed >= seeds.end {
+                        break results;
+                    }
+                    results.push(self.play(&mut *planner, seed));
+                }
+            })).collect();
+            handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+        });
+        results.sort_by_key(|game| game.seed);
+        results
+    }
+
+    fn is_finished(&self, tetris: &Tetris) -> bool {
+        tetris.get_shape_count() >= self.max_pieces ||
+            (self.mode == Mode::Sprint && tetris.get_rows_completed() >= SPRINT_ROWS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use ai::{BeamSearch, Bot, Weights};
+
+    /// Returns a search that always reaches its full depth, so the placements don't depend on
+    /// how fast the threads run
+    fn beam_search(threads: usize) -> BeamSearch {
+        let mut search = BeamSearch::new(Bot::new(Weights::default()), 4, 2, Duration::from_secs(600));
+        search.set_threads(threads);
+        search
+    }
+
+    fn stats(games: &[GameStats]) -> Vec<(u64, u32, u32, u32, u32, bool)> {
+        games.iter().map(|game| (game.seed, game.rows, game.score, game.attack, game.pieces, game.topped_out)).collect()
+    }
+
+    #[test]
+    fn the_search_chooses_the_same_placements_on_any_number_of_threads() {
+        let (mut single, mut several) = (beam_search(1), beam_search(4));
+        let mut tetris = Tetris::new();
+        tetris.set_seed(3);
+        tetris.start_game();
+        for _ in 0..12 {
+            let placement = single.choose(&tetris).unwrap();
+            let other = several.choose(&tetris).unwrap();
+            assert_eq!((placement.shape_index, placement.get_cells()), (other.shape_index, other.get_cells()));
+            assert_eq!(placement.path, other.path);
+            for input in placement.path {
+                tetris.apply_input(input);
+            }
+        }
+    }
+
+    #[test]
+    fn games_on_several_threads_match_games_on_one() {
+        let simulation = Simulation { max_pieces: 20, ..Simulation::default() };
+        let games = simulation.play_games(&mut beam_search(1), 0..4);
+        let parallel = simulation.play_games_parallel(|| Box::new(beam_search(2)), 0..4, 3);
+        assert_eq!(stats(&games), stats(&parallel));
+        // and the same seed plays the same game again
+        assert_eq!(stats(&[simulation.play(&mut beam_search(1), 2)]), stats(&games[2..3]));
+    }
+}
@@ -0,0 +1,476 @@
+use std::collections::VecDeque;
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json;
+
+use ai::{Board, Planner};
+use tetris::*;
+
+/// The number of rows of the board sent to a bot. The board is taller than the visible board,
+/// and the rows above it are always empty.
+const BOARD_HEIGHT: i32 = 40;
+
+/// The time a bot has to start up and accept the rules
+const START_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The time a bot has to suggest a move by default, see also TbpBot::set_timeout()
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The name of each shape in TBP messages, in the order of the SHAPES const
+const PIECE_NAMES: [&str; SHAPE_COUNT as usize] = ["T", "O", "Z", "S", "L", "J", "I"];
+
+/// The name of each orientation in TBP messages, each a clockwise rotation of the one before
+const ORIENTATION_NAMES: [&str; 4] = ["north", "east", "south", "west"];
+
+/// The points of each shape facing north, relative to the center TBP uses for the shape's
+/// location. Unlike the SHAPES const, y increases upwards. In the order of PIECE_NAMES.
+const PIECE_POINTS: [[(i32, i32); POINT_COUNT as usize]; SHAPE_COUNT as usize] = [
+    [(-1, 0), (0, 0), (1, 0), (0, 1)],
+    [(0, 0), (1, 0), (0, 1), (1, 1)],
+    [(-1, 1), (0, 1), (0, 0), (1, 0)],
+    [(-1, 0), (0, 0), (0, 1), (1, 1)],
+    [(-1, 0), (0, 0), (1, 0), (1, 1)],
+    [(-1, 0), (0, 0), (1, 0), (-1, 1)],
+    [(-1, 0), (0, 0), (1, 0), (2, 0)],
+];
+
+/// Where a move places a shape. x and y are the column and row of the shape's center,
+/// counting rows up from the bottom of the board.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PieceLocation {
+    #[serde(rename = "type")]
+    pub piece: String,
+    pub orientation: String,
+    pub x: i32,
+    pub y: i32,
+}
+
+/// A move suggested by a bot. spin is "none", "mini" or "full".
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Move {
+    pub location: PieceLocation,
+    pub spin: String,
+}
+
+/// The messages sent to a bot
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum FrontendMessage {
+    Rules,
+    /// Starts a game. The queue starts with the current shape, and each cell of the board is
+    /// null or the name of the shape that filled it, listed row by row from the bottom.
+    Start {
+        hold: Option<String>,
+        queue: Vec<String>,
+        combo: u32,
+        back_to_back: bool,
+        board: Vec<Vec<Option<String>>>,
+    },
+    Suggest,
+    Play {
+        #[serde(rename = "move")]
+        placement: Move,
+    },
+    NewPiece { piece: String },
+    Stop,
+    Quit,
+}
+
+/// The messages sent by a bot
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum BotMessage {
+    Info { name: String, version: String, author: String },
+    Ready,
+    Error { reason: String },
+    /// The moves the bot would play, best first
+    Suggestion { moves: Vec<Move> },
+}
+
+/// The game the bot has been told about, used to tell whether the next shape can carry on
+/// the same game, or the game changed in a way the bot can't know about, e.g. garbage arrived
+struct BotGame {
+    board: Board,
+    hold_shape_index: i32,
+    /// The current shape followed by the next queue, as far as the bot knows
+    queue: VecDeque<i32>,
+    shape_count: u32,
+}
+
+/// A TbpBot is an external bot process speaking the Tetris Bot Protocol (TBP), where each
+/// message is a line of JSON exchanged over the bot's stdin and stdout. This lets bots like
+/// Cold Clear play the game, see also Planner.
+pub struct TbpBot {
+    child: Child,
+    stdin: ChildStdin,
+    /// The lines the bot writes to stdout, read on a separate thread so a slow or hung bot
+    /// never blocks the game for longer than the timeout
+    lines: mpsc::Receiver<io::Result<String>>,
+    /// The time the bot has to answer each message
+    timeout: Duration,
+    /// The name, version and author reported by the bot
+    name: String,
+    /// The game the bot is playing, or None if the bot is waiting to start a game
+    game: Option<BotGame>,
+    /// Set after the bot process fails, so the failure is only reported once
+    failed: bool,
+}
+
+impl TbpBot {
+    /// Starts a bot process, e.g. ["cold-clear"], and waits until it's ready to play
+    pub fn spawn(command: &[String]) -> io::Result<TbpBot> {
+        let (program, args) = command.split_first()
+            .ok_or_else(|| invalid_data("Missing bot command"))?;
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            // the thread ends when the bot closes stdout, or the TbpBot is dropped
+            for line in stdout.lines() {
+                let failed = line.is_err();
+                if sender.send(line).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        let mut result = TbpBot {
+            child,
+            stdin,
+            lines,
+            timeout: START_TIMEOUT,
+            name: String::new(),
+            game: None,
+            failed: false,
+        };
+
+        match result.receive()? {
+            BotMessage::Info { name, version, author } => {
+                result.name = format!("{} {} by {}", name, version, author);
+            },
+            _ => return Err(invalid_data("Expected an info message")),
+        }
+        result.send(&FrontendMessage::Rules)?;
+        match result.receive()? {
+            BotMessage::Ready => {
+                result.timeout = DEFAULT_TIMEOUT;
+                Ok(result)
+            },
+            BotMessage::Error { reason } => Err(invalid_data(&format!("Bot rejected the rules: {}", reason))),
+            _ => Err(invalid_data("Expected a ready message")),
+        }
+    }
+
+    /// Sets the time the bot has to suggest each move. A bot that takes longer is stopped.
+    pub fn set_timeout(&mut self, value: Duration) {
+        self.timeout = value;
+    }
+
+    /// Returns the name, version and author reported by the bot
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    fn send(&mut self, message: &FrontendMessage) -> io::Result<()> {
+        let mut line = serde_json::to_string(message)?;
+        line.push('\n');
+        self.stdin.write_all(line.as_bytes())?;
+        self.stdin.flush()
+    }
+
+    /// Waits up to the timeout for the next message from the bot, skipping messages this game
+    /// doesn't know about
+    fn receive(&mut self) -> io::Result<BotMessage> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            let line = match self.lines.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(line) => line?,
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut,
+                        format!("Bot didn't answer within {} ms", self.timeout.as_millis())));
+                },
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Bot exited"));
+                },
+            };
+            match serde_json::from_str(&line) {
+                Ok(message) => return Ok(message),
+                // later versions of the protocol may add messages, which are ignored
+                Err(_) if serde_json::from_str::<serde_json::Value>(&line).is_ok() => {},
+                Err(error) => return Err(error.into()),
+            }
+        }
+    }
+
+    /// Tells the bot about the current game, starting a new game unless the bot's game
+    /// already matches, then asks the bot for its moves and plays the first move possible
+    fn suggest(&mut self, tetris: &Tetris) -> io::Result<Option<Placement>> {
+        let board = Board::from_tetris(tetris);
+        let queue: VecDeque<i32> = Some(tetris.get_shape_index()).into_iter()
+            .chain(tetris.get_next_queue().iter().cloned())
+            .collect();
+        let same_game = self.game.as_ref().is_some_and(|game| {
+            game.board == board &&
+                game.hold_shape_index == tetris.get_hold_shape_index() &&
+                game.shape_count == tetris.get_shape_count() &&
+                game.queue.iter().zip(queue.iter()).all(|(a, b)| a == b)
+        });
+
+        if same_game {
+            // only the shapes added to the queue since the last move are new to the bot
+            let known = self.game.as_ref().unwrap().queue.len();
+            for shape_index in queue.iter().skip(known) {
+                let piece = PIECE_NAMES[*shape_index as usize].to_string();
+                self.send(&FrontendMessage::NewPiece { piece })?;
+            }
+            self.game.as_mut().unwrap().queue = queue;
+        } else {
+            if self.game.is_some() {
+                self.send(&FrontendMessage::Stop)?;
+            }
+            let hold_shape_index = tetris.get_hold_shape_index();
+            self.send(&FrontendMessage::Start {
+                hold: if hold_shape_index >= 0 { Some(PIECE_NAMES[hold_shape_index as usize].to_string()) } else { None },
+                queue: queue.iter().map(|shape_index| PIECE_NAMES[*shape_index as usize].to_string()).collect(),
+                combo: (tetris.get_combo() + 1) as u32,
+                back_to_back: tetris.get_back_to_back(),
+                board: board_rows(tetris),
+            })?;
+            self.game = Some(BotGame {
+                board,
+                hold_shape_index,
+                queue,
+                shape_count: tetris.get_shape_count(),
+            });
+        }
+
+        self.send(&FrontendMessage::Suggest)?;
+        let moves = loop {
+            match self.receive()? {
+                BotMessage::Suggestion { moves } => break moves,
+                BotMessage::Error { reason } => return Err(invalid_data(&reason)),
+                _ => {},
+            }
+        };
+
+        let placements = tetris.get_placements(true);
+        let found = moves.into_iter()
+            .filter_map(|bot_move| find_placement(&placements, &bot_move).map(|placement| (bot_move, placement)))
+            .next();
+        match found {
+            Some((bot_move, placement)) => {
+                self.send(&FrontendMessage::Play { placement: bot_move })?;
+                let game = self.game.as_mut().unwrap();
+                game.board.place(&placement);
+                game.shape_count += 1;
+                let current_shape_index = game.queue.pop_front().unwrap();
+                if placement.path.first() == Some(&Input::Hold) {
+                    // holding with nothing held also plays the next shape in the queue
+                    if game.hold_shape_index < 0 {
+                        game.queue.pop_front();
+                    }
+                    game.hold_shape_index = current_shape_index;
+                }
+                Ok(Some(placement))
+            },
+            None => {
+                // none of the moves can be played here, so start over with the next shape
+                self.send(&FrontendMessage::Stop)?;
+                self.game = None;
+                Ok(None)
+            },
+        }
+    }
+}
+
+impl Planner for TbpBot {
+    /// Returns the first placement suggested by the bot that can be played, or None if the
+    /// bot suggested no such placement or stopped working
+    fn choose(&mut self, tetris: &Tetris) -> Option<Placement> {
+        if self.failed {
+            return None;
+        }
+        if tetris.get_game_over() {
+            if self.game.take().is_some() && self.send(&FrontendMessage::Stop).is_err() {
+                self.failed = true;
+            }
+            return None;
+        }
+        match self.suggest(tetris) {
+            Ok(placement) => placement,
+            Err(error) => {
+                eprintln!("Bot {} failed: {}", self.name, error);
+                self.failed = true;
+                None
+            },
+        }
+    }
+}
+
+impl Drop for TbpBot {
+    fn drop(&mut self) {
+        // a bot that hung might never read the quit message, so it's killed rather than waited on
+        let _ = self.send(&FrontendMessage::Quit);
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn invalid_data(text: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, text)
+}
+
+/// Returns the Fixed cells of the board in TBP order, row by row from the bottom
+fn board_rows(tetris: &Tetris) -> Vec<Vec<Option<String>>> {
+    (0..BOARD_HEIGHT).map(|y| {
+        let row = ROW_COUNT as i32 - 1 - y;
+        (0..COL_COUNT as i32).map(|col| {
+            if row < 0 {
+                return None;
+            }
+            let cell = tetris.get_grid_cell(col, row);
+            if cell.cell_type != GridCellType::Fixed {
+                None
+            } else if cell.shape_index == GARBAGE_SHAPE_INDEX {
+                Some("G".to_string())
+            } else {
+                Some(PIECE_NAMES[cell.shape_index as usize].to_string())
+            }
+        }).collect()
+    }).collect()
+}
+
+/// Returns the placement covering the same cells as a move. A T-spin move prefers a
+/// placement rotated into place, where the rotation is the last input before the hard drop,
+/// as Tetris::get_placements() only counts a spin when the shape didn't drop afterwards.
+fn find_placement(placements: &[Placement], bot_move: &Move) -> Option<Placement> {
+    let location = &bot_move.location;
+    let shape_index = PIECE_NAMES.iter().position(|name| *name == location.piece)?;
+    let rotations = ORIENTATION_NAMES.iter().position(|name| *name == location.orientation)?;
+    let mut cells: Vec<Point> = PIECE_POINTS[shape_index].iter().map(|&(x, y)| {
+        let (mut x, mut y) = (x, y);
+        for _ in 0..rotations {
+            // rotate clockwise, where y increases upwards
+            let temp = x;
+            x = y;
+            y = -temp;
+        }
+        Point { x: (location.x + x) as i16, y: (ROW_COUNT as i32 - 1 - (location.y + y)) as i16 }
+    }).collect();
+    cells.sort();
+
+    let spin = bot_move.spin != "none";
+    let mut matching = placements.iter().filter(|placement| {
+        let mut placement_cells = placement.get_cells();
+        placement_cells.sort();
+        placement.shape_index == shape_index as i32 && placement_cells[..] == cells[..]
+    });
+    let first = matching.next()?;
+    let rotated_last = |placement: &Placement| placement.path.iter().rev()
+        .find(|input| **input != Input::HardDrop)
+        .is_some_and(|input| *input == Input::RotateClockwise || *input == Input::RotateCounterClockwise ||
+            *input == Input::Rotate180);
+    if rotated_last(first) == spin {
+        Some(first.clone())
+    } else {
+        Some(matching.find(|placement| rotated_last(placement) == spin).unwrap_or(first).clone())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    /// A script that plays a TBP bot, answering the rules with ready and each request for a
+    /// suggestion with the given line
+    fn stub_bot(suggestion: &str) -> String {
+        format!(r#"echo '{{"type":"info","name":"Stub","version":"1.0","author":"Tests"}}'
+while read -r line; do
+    case "$line" in
+        *'"type":"rules"'*) echo '{{"type":"ready"}}' ;;
+        *'"type":"suggest"'*) {} ;;
+        *'"type":"quit"'*) exit 0 ;;
+    esac
+done"#, suggestion)
+    }
+
+    fn spawn(script: &str) -> io::Result<TbpBot> {
+        TbpBot::spawn(&["sh".to_string(), "-c".to_string(), script.to_string()])
+    }
+
+    fn new_game() -> Tetris {
+        let mut tetris = Tetris::new();
+        tetris.set_seed(3);
+        tetris.start_game();
+        tetris
+    }
+
+    /// Suggests every shape lying flat on the floor, so one of them is the current or the next shape
+    const FLAT_MOVES: &str = r#"echo '{"type":"suggestion","moves":[
+        {"location":{"type":"T","orientation":"north","x":1,"y":0},"spin":"none"},
+        {"location":{"type":"O","orientation":"north","x":0,"y":0},"spin":"none"},
+        {"location":{"type":"Z","orientation":"north","x":1,"y":0},"spin":"none"},
+        {"location":{"type":"S","orientation":"north","x":1,"y":0},"spin":"none"},
+        {"location":{"type":"L","orientation":"north","x":1,"y":0},"spin":"none"},
+        {"location":{"type":"J","orientation":"north","x":1,"y":0},"spin":"none"},
+        {"location":{"type":"I","orientation":"north","x":1,"y":0},"spin":"none"}]}' | tr -d '\n'; echo"#;
+
+    #[test]
+    fn bot_suggestions_are_played() {
+        let mut bot = spawn(&stub_bot(FLAT_MOVES)).unwrap();
+        assert_eq!(bot.get_name(), "Stub 1.0 by Tests");
+        let tetris = new_game();
+        let placement = bot.choose(&tetris).expect("the bot should suggest a move");
+        assert!(placement.shape_index == tetris.get_shape_index() || placement.path[0] == Input::Hold);
+        assert!(placement.get_cells().iter().all(|point| point.x <= 3 && point.y >= ROW_COUNT as i16 - 2));
+        assert!(!bot.failed);
+    }
+
+    #[test]
+    fn bot_rejecting_the_rules_fails_to_start() {
+        let script = r#"echo '{"type":"info","name":"Stub","version":"1.0","author":"Tests"}'
+read -r line
+echo '{"type":"error","reason":"unsupported rules"}'"#;
+        let error = spawn(script).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("unsupported rules"));
+    }
+
+    #[test]
+    fn bot_that_exits_fails_to_start() {
+        assert!(spawn("exit 0").is_err());
+    }
+
+    #[test]
+    fn hung_bot_times_out_and_is_killed() {
+        let mut bot = spawn(&stub_bot("sleep 30")).unwrap();
+        bot.set_timeout(Duration::from_millis(100));
+        let tetris = new_game();
+        let started = Instant::now();
+        assert!(bot.choose(&tetris).is_none());
+        // once failed, the bot isn't asked again
+        assert!(bot.choose(&tetris).is_none());
+        drop(bot);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn unknown_moves_are_skipped() {
+        let suggestion = r#"echo '{"type":"suggestion","moves":[{"location":{"type":"I","orientation":"east","x":-5,"y":30},"spin":"none"}]}'"#;
+        let mut bot = spawn(&stub_bot(suggestion)).unwrap();
+        let tetris = new_game();
+        assert!(bot.choose(&tetris).is_none());
+        // the bot still works, and starts over with the next request
+        assert!(bot.choose(&tetris).is_none());
+        assert!(!bot.failed);
+    }
+}
@@ -1,10 +1,12 @@
 extern crate rand;
-use rand::Rng;
+use rand::{FromEntropy, Rng, SeedableRng};
+use rand::rngs::StdRng;
+use std::collections::VecDeque;
 
 /// A Point represents a portion of a Shape (or tetromino).
 /// There are 4 points per shape, and each point represents
 /// an x/y coordinate offset from a center position.
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
 pub struct Point {
     pub x: i16,
     pub y: i16,
@@ -23,6 +25,12 @@ pub const POINT_COUNT: u8 = 4;
 /// The number of rows the player must complete before going to a new level
 pub const ROWS_PER_LEVEL: u8 = 10;
 
+/// The number of upcoming shapes the player can see, see also Tetris::get_next_queue()
+pub const PREVIEW_COUNT: u8 = 5;
+
+/// The bit mask of a row with every column filled, see also Tetris::get_row_bits()
+pub const FULL_ROW: u16 = (1 << COL_COUNT) - 1;
+
 /// Each tetromino shape is defined by the SHAPES constant.
 /// There are 4 points per shape, and 7 shapes in all.
 /// So SHAPES is a two-dimensional array to get access to 
@@ -76,6 +84,433 @@ pub const SHAPES: [[Point; POINT_COUNT as usize]; SHAPE_COUNT as usize] = [
 /// presses the rotate key
 pub const SQUARE_SHAPE_INDEX: i32 = 1;
 
+/// The T shape can score T-spins, see also LockResult
+pub const T_SHAPE_INDEX: i32 = 0;
+
+/// The offsets (col, row) tried in turn when turning a shape around with Tetris::rotate_180(), until
+/// the shape fits. Rows grow downwards, so a row offset of -1 kicks the shape up. Similar to the
+/// 180 kicks of TETR.IO.
+pub const KICKS_180: [(i32, i32); 6] = [(0, 0), (0, -1), (1, 0), (-1, 0), (1, -1), (-1, -1)];
+
+/// Rows of garbage sent by an opponent are not part of any shape, so their Fixed cells
+/// use this shape_index instead
+pub const GARBAGE_SHAPE_INDEX: i32 = SHAPE_COUNT as i32;
+
+/// Garbage is a group of rows sent by an opponent, waiting in a queue to be pushed onto the
+/// bottom of the game board. Every garbage row is filled except for the hole column.
+#[derive(Copy, Clone)]
+pub struct Garbage {
+    /// The number of rows of garbage
+    pub rows: u32,
+    /// The column left empty in each row, so that the rows can still be completed
+    pub hole_col: i32,
+}
+
+/// The kind of T-spin performed when a T shape is fixed into place. A T-spin requires the last
+/// move of the shape to be a rotation, and at least three of the four cells diagonal to the
+/// center of the T to be blocked. A Mini T-spin is one where only one of the two cells on the
+/// pointing side of the T is blocked.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum TSpin {
+    None,
+    Mini,
+    Full,
+}
+
+/// The bonus attack for completing rows on consecutive shapes (a combo)
+#[derive(Clone, Debug)]
+pub enum ComboAttack {
+    /// Attack added for each combo count, the last entry is used for any longer combo
+    Table(Vec<u32>),
+    /// Attack is multiplied by 1 + (multiplier * combo count). A combo of rows that would
+    /// otherwise send nothing still sends a little attack, growing slowly with the combo.
+    Multiplier(f32),
+}
+
+/// The AttackTable determines how many garbage rows are sent to an opponent each time
+/// a shape is fixed into place, see also LockResult
+#[derive(Clone, Debug)]
+pub struct AttackTable {
+    /// Attack for completing 0, 1, 2, 3 or 4 rows at once without a T-spin
+    pub rows: [u32; 5],
+    /// Attack for completing 0, 1 or 2 rows with a Mini T-spin
+    pub t_spin_mini: [u32; 3],
+    /// Attack for completing 0, 1, 2 or 3 rows with a T-spin
+    pub t_spin: [u32; 4],
+    /// Extra attack for a back to back, e.g. completing 4 rows or a T-spin after the last
+    /// rows were also completed with 4 rows or a T-spin
+    pub back_to_back: u32,
+    /// Extra attack for completing rows on consecutive shapes
+    pub combo: ComboAttack,
+    /// Extra attack for clearing every cell from the game board
+    pub perfect_clear: u32,
+}
+
+impl AttackTable {
+    /// Attack similar to the standard set by the Tetris guideline
+    pub fn guideline() -> AttackTable {
+        AttackTable {
+            rows: [0, 0, 1, 2, 4],
+            t_spin_mini: [0, 0, 1],
+            t_spin: [0, 2, 4, 6],
+            back_to_back: 1,
+            combo: ComboAttack::Table(vec![0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5]),
+            perfect_clear: 10,
+        }
+    }
+
+    /// Attack similar to TETR.IO, where combos multiply the attack rather than add to it
+    pub fn tetrio() -> AttackTable {
+        AttackTable {
+            rows: [0, 0, 1, 2, 4],
+            t_spin_mini: [0, 0, 1],
+            t_spin: [0, 2, 4, 6],
+            back_to_back: 1,
+            combo: ComboAttack::Multiplier(0.25),
+            perfect_clear: 10,
+        }
+    }
+
+    /// Finds an attack table by name, either "guideline" or "tetrio"
+    pub fn from_name(name: &str) -> Option<AttackTable> {
+        match name {
+            "guideline" => Some(AttackTable::guideline()),
+            "tetrio" => Some(AttackTable::tetrio()),
+            _ => None,
+        }
+    }
+
+    /// Calculates the attack for the rows completed by a shape
+    pub fn attack(&self, rows: u8, t_spin: TSpin, combo: u32, back_to_back: bool, perfect_clear: bool) -> u32 {
+        if rows == 0 {
+            return 0;
+        }
+        let rows = rows as usize;
+        let mut result = match t_spin {
+            TSpin::None => self.rows[std::cmp::min(rows, 4)],
+            TSpin::Mini => self.t_spin_mini[std::cmp::min(rows, 2)],
+            TSpin::Full => self.t_spin[std::cmp::min(rows, 3)],
+        };
+        if back_to_back {
+            result += self.back_to_back;
+        }
+        result = match self.combo {
+            ComboAttack::Table(ref table) => match table.last() {
+                Some(last) => result + *table.get(combo as usize).unwrap_or(last),
+                None => result,
+            },
+            ComboAttack::Multiplier(multiplier) => if result > 0 {
+                (result as f32 * (1f32 + multiplier * combo as f32)) as u32
+            } else {
+                (1f32 + 1.25f32 * combo as f32).ln() as u32
+            },
+        };
+        if perfect_clear {
+            result += self.perfect_clear;
+        }
+        result
+    }
+}
+
+impl Default for AttackTable {
+    fn default() -> AttackTable {
+        AttackTable::guideline()
+    }
+}
+
+/// The result of fixing a shape into place, returned by Tetris::tick()
+#[derive(Copy, Clone, Debug)]
+pub struct LockResult {
+    /// The number of rows completed by the shape
+    pub rows: u8,
+    /// The kind of T-spin, if the shape was a T shape
+    pub t_spin: TSpin,
+    /// The number of consecutive shapes before this one that also completed rows
+    pub combo: u32,
+    /// True if the rows were completed back to back, see also AttackTable
+    pub back_to_back: bool,
+    /// True if no cells were left on the game board after completing rows
+    pub perfect_clear: bool,
+    /// The score earned by the shape
+    pub score: u32,
+    /// The number of garbage rows to send to an opponent, see also AttackTable
+    pub attack: u32,
+    /// The grid position of each point of the shape when it was fixed into place, before any
+    /// rows were removed
+    pub cells: [Point; POINT_COUNT as usize],
+}
+
+/// An Input is a single action applied to a game, see Tetris::apply_input(). Because a game
+/// started with a known seed always produces the same shapes, recording the inputs applied to
+/// the game is enough to replay it, e.g. to show an opponent's game over the network.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Input {
+    Left,
+    Right,
+    RotateClockwise,
+    RotateCounterClockwise,
+    /// Turns the shape around, see also Tetris::rotate_180()
+    Rotate180,
+    SoftDrop,
+    HardDrop,
+    /// Swaps the current shape with the held shape, see also Tetris::hold()
+    Hold,
+    /// Advances the game, as if tick() were invoked
+    Tick,
+}
+
+/// A Placement is a position where the current shape can be fixed into place, along with the
+/// inputs needed to get there, see Tetris::get_placements()
+#[derive(Clone, Debug)]
+pub struct Placement {
+    /// The shape placed, which is the held or next shape if the path starts with Input::Hold
+    pub shape_index: i32,
+    /// The points of the shape after any rotations
+    pub shape: [Point; POINT_COUNT as usize],
+    /// The number of clockwise rotations from the shape's starting position, 0 to 3
+    pub rotation: u8,
+    /// The column of the shape when fixed into place
+    pub col: i32,
+    /// The row of the shape when fixed into place
+    pub row: i32,
+    /// The inputs that move the shape into place, ending with Input::HardDrop
+    pub path: Vec<Input>,
+    /// The keys pressed to follow the path, each with the number of times its input is applied.
+    /// Holding left or right until the shape reaches the wall, or holding soft drop, is a single key.
+    pub keys: Vec<(Input, u8)>,
+}
+
+impl Placement {
+    /// Returns the grid position of each point of the shape
+    pub fn get_cells(&self) -> [Point; POINT_COUNT as usize] {
+        let mut result = self.shape;
+        for point in result.iter_mut() {
+            point.x += self.col as i16;
+            point.y += self.row as i16;
+        }
+        result
+    }
+
+    /// Returns the number of keys pressed to follow the path, see also Placement::keys
+    pub fn get_input_count(&self) -> usize {
+        self.keys.len()
+    }
+}
+
+/// A BoardAnalysis measures the Fixed cells of a board, the features that bots, coaching and
+/// stats look at, see Tetris::analyze_board(). The current shape isn't included.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct BoardAnalysis {
+    rows: [u16; ROW_COUNT as usize],
+}
+
+impl BoardAnalysis {
+    /// Analyzes rows of bit masks, see also Tetris::get_row_bits()
+    pub fn new(rows: &[u16; ROW_COUNT as usize]) -> BoardAnalysis {
+        BoardAnalysis { rows: *rows }
+    }
+
+    /// Returns the height of a column, from the floor to its highest filled cell
+    pub fn column_height(&self, col: i32) -> u32 {
+        match self.rows.iter().position(|bits| bits & (1 << col) != 0) {
+            Some(row) => ROW_COUNT as u32 - row as u32,
+            None => 0,
+        }
+    }
+
+    pub fn column_heights(&self) -> [u32; COL_COUNT as usize] {
+        let mut result = [0; COL_COUNT as usize];
+        for (col, height) in result.iter_mut().enumerate() {
+            *height = self.column_height(col as i32);
+        }
+        result
+    }
+
+    /// Returns the sum of the differences in height between neighbouring columns
+    pub fn bumpiness(&self) -> u32 {
+        self.column_heights().windows(2).map(|pair| (pair[0] as i32 - pair[1] as i32).unsigned_abs()).sum()
+    }
+
+    /// Returns the empty cells with a filled cell somewhere above them in the same column,
+    /// from the top row down
+    pub fn holes(&self) -> Vec<Point> {
+        let mut result = Vec::new();
+        let mut covered = 0u16;
+        for (row, bits) in self.rows.iter().enumerate() {
+            let holes = !bits & covered;
+            for col in 0..COL_COUNT as i16 {
+                if holes & (1 << col) != 0 {
+                    result.push(Point { x: col, y: row as i16 });
+                }
+            }
+            covered |= bits;
+        }
+        result
+    }
+
+    /// Returns the number of holes, see also holes()
+    pub fn hole_count(&self) -> u32 {
+        let mut result = 0;
+        // the columns with a filled cell in any row above
+        let mut covered = 0u16;
+        for bits in self.rows.iter() {
+            result += (!bits & covered).count_ones();
+            covered |= bits;
+        }
+        result
+    }
+
+    /// Returns the number of filled cells above a hole in the same column, the cells that must
+    /// be cleared before the holes can be filled
+    pub fn covered_cells(&self) -> u32 {
+        // the columns with a filled cell in any row above each row
+        let mut covered = [0u16; ROW_COUNT as usize];
+        for row in 1..ROW_COUNT as usize {
+            covered[row] = covered[row - 1] | self.rows[row - 1];
+        }
+        let mut result = 0;
+        // the columns with a hole in any row below
+        let mut over_hole = 0u16;
+        for (row, bits) in self.rows.iter().enumerate().rev() {
+            result += (bits & over_hole).count_ones();
+            over_hole |= !bits & covered[row];
+        }
+        result
+    }
+
+    /// Returns the depth of the well in each column, how far the column is below the lower of its
+    /// neighbours. The walls count as neighbours higher than the board.
+    pub fn well_depths(&self) -> [u32; COL_COUNT as usize] {
+        let heights = self.column_heights();
+        let mut result = [0; COL_COUNT as usize];
+        for (col, depth) in result.iter_mut().enumerate() {
+            let left = if col > 0 { heights[col - 1] } else { ROW_COUNT as u32 };
+            let right = heights.get(col + 1).cloned().unwrap_or(ROW_COUNT as u32);
+            *depth = left.min(right).saturating_sub(heights[col]);
+        }
+        result
+    }
+
+    /// Returns the sum over every well cell, an empty cell with filled cells or walls on both
+    /// sides, of its depth from the top of its well. Deep wells count far more than shallow ones.
+    pub fn well_sum(&self) -> u32 {
+        let mut result = 0;
+        let mut depths = [0u32; COL_COUNT as usize];
+        for bits in self.rows.iter() {
+            let padded = Self::padded(*bits);
+            let well_cells = !bits & FULL_ROW & (padded >> 2) & padded;
+            for (col, depth) in depths.iter_mut().enumerate() {
+                if well_cells & (1 << col) != 0 {
+                    *depth += 1;
+                    result += *depth;
+                } else {
+                    *depth = 0;
+                }
+            }
+        }
+        result
+    }
+
+    /// Returns the number of times a filled cell is next to an empty cell in the same row,
+    /// counting the walls as filled
+    pub fn row_transitions(&self) -> u32 {
+        self.rows.iter()
+            .map(|bits| {
+                let padded = Self::padded(*bits);
+                ((padded ^ (padded >> 1)) & ((1 << (COL_COUNT + 1)) - 1)).count_ones()
+            })
+            .sum()
+    }
+
+    /// Returns the number of times a filled cell is above or below an empty cell in the same
+    /// column, counting the floor as filled
+    pub fn column_transitions(&self) -> u32 {
+        self.rows.iter().enumerate()
+            .map(|(row, bits)| (bits ^ self.rows.get(row + 1).cloned().unwrap_or(FULL_ROW)).count_ones())
+            .sum()
+    }
+
+    /// Returns the most rows that a single shape could complete at once, dropping any shape in
+    /// any rotation straight down into any column
+    pub fn max_clearable_lines(&self) -> u32 {
+        let mut result = 0;
+        for shape in SHAPES.iter() {
+            let mut shape = *shape;
+            for _ in 0..4 {
+                for col in 0..COL_COUNT as i32 {
+                    result = result.max(self.drop_lines(&shape, col));
+                }
+                // rotate clockwise, as Tetris::rotate_shape() does
+                for point in shape.iter_mut() {
+                    let old_y = point.y;
+                    point.y = point.x;
+                    point.x = -old_y;
+                }
+            }
+        }
+        result
+    }
+
+    /// Returns the rows completed by dropping a shape straight down into a column, or 0 if it
+    /// doesn't fit
+    fn drop_lines(&self, shape: &[Point; POINT_COUNT as usize], col: i32) -> u32 {
+        let fits = |row: i32| shape.iter().all(|point| {
+            let (x, y) = (col + point.x as i32, row + point.y as i32);
+            x >= 0 && x < COL_COUNT as i32 && y < ROW_COUNT as i32 &&
+                (y < 0 || self.rows[y as usize] & (1 << x) == 0)
+        });
+        // start with every point above the grid
+        let mut row = -(SEARCH_MARGIN + 1);
+        if !fits(row) {
+            return 0;
+        }
+        while fits(row + 1) {
+            row += 1;
+        }
+        let mut rows = self.rows;
+        for point in shape.iter() {
+            let y = row + point.y as i32;
+            if y < 0 {
+                return 0;
+            }
+            rows[y as usize] |= 1 << (col + point.x as i32);
+        }
+        rows.iter().filter(|bits| **bits == FULL_ROW).count() as u32
+    }
+
+    /// Returns a row with a filled bit added on each side for the walls, so column n is bit n + 1
+    fn padded(bits: u16) -> u16 {
+        (bits << 1) | 1 | (1 << (COL_COUNT + 1))
+    }
+}
+
+/// Returns the number of keys pressed to apply a sequence of inputs, counting each run of soft
+/// drops as a single key held down. Input::Tick isn't a key, so it isn't counted.
+pub fn count_inputs(inputs: &[Input]) -> usize {
+    inputs.iter().enumerate()
+        .filter(|&(index, input)| *input != Input::Tick &&
+            (*input != Input::SoftDrop || index == 0 || inputs[index - 1] != Input::SoftDrop))
+        .count()
+}
+
+/// A position and rotation of a shape visited while searching for placements
+#[derive(Copy, Clone)]
+struct SearchState {
+    col: i32,
+    row: i32,
+    rotation: u8,
+    shape: [Point; POINT_COUNT as usize],
+    /// The index of the state this state was reached from, or None for the starting position
+    parent: Option<usize>,
+    /// The input that reached this state from the parent, pressed count times
+    input: Input,
+    count: u8,
+}
+
+/// The furthest a shape's position can be outside the grid, since the points of a shape are
+/// at most 2 cells from its position
+const SEARCH_MARGIN: i32 = 2;
+
 /// The tetris game board consists of a two-dimensional array of GridCell's. Each GridCell struct
 /// contains an enum, GridCellType to indicate the type of cell
 #[derive(Copy, Clone, PartialEq)]
@@ -89,6 +524,9 @@ pub enum GridCellType {
     /// Ghost means the cell is a point of a "ghost shape", used for previewing where a shape
     /// will be dropped.
     Ghost,
+    /// Hint means the cell is a point of a placement recommended to the player. The game never
+    /// puts Hint cells in its own grid, they're for drawing overlays over the empty and ghost cells.
+    Hint,
 }
 
 /// The tetris game board consists of a two-dimensional array of GridCell's.
@@ -117,16 +555,23 @@ impl Default for GridCell {
 /// shape is determined at random. To render the game board, users can invoke the
 /// method, get_grid_cell(col, row) for each cell to determine what color should be
 /// painted at that cell, or paint nothing if the cell is void.
+/// Cloning a Tetris struct takes a snapshot of the whole game, including the random number generator.
+/// A Tetris struct is Send, so games can be searched or played on other threads.
+#[derive(Clone)]
 pub struct Tetris {
     /// The game board as a two dimensional array of GridCell's
     grid: [[GridCell; ROW_COUNT as usize]; COL_COUNT as usize],
+    /// The Fixed cells of each row as a bit mask, where bit n is set if column n is filled.
+    /// Collisions and completed rows are tested against these, and grid keeps the rest: the
+    /// shape_index (color) of each cell, and the moving and ghost shapes.
+    row_bits: [u16; ROW_COUNT as usize],
     /// Game over flag
     game_over: bool,
+    /// True while the game is paused, see also set_paused()
+    paused: bool,
     /// The current shape equal to the corresponding shape in the SHAPES const
     /// unless the shape has been rotated
     shape: [Point; POINT_COUNT as usize],
-    /// The next randomly determined shape, see also next_shape_index
-    next_shape: [Point; POINT_COUNT as usize],
     /// The column position of the current moving shape
     col: i32,
     /// The row position of the current moving shape
@@ -135,8 +580,14 @@ pub struct Tetris {
     ghost_row: i32,
     /// The current shape index into the SHAPES const
     shape_index: i32,
-    /// The next random shape index into the SHAPES const
-    next_shape_index: i32,
+    /// The next random shape indices into the SHAPES const, in the order they will be added on the board
+    next_queue: VecDeque<i32>,
+    /// The number of clockwise rotations of the current shape from its starting position, 0 to 3
+    rotation: u8,
+    /// The held shape index into the SHAPES const, or -1 if no shape is held
+    hold_shape_index: i32,
+    /// False once the current shape has been held, until the next shape is added on the board
+    can_hold: bool,
     /// The current level number
     level: u32,
     /// The starting level when the game starts
@@ -147,8 +598,28 @@ pub struct Tetris {
     score: u32,
     /// The total number of rows completed
     rows_completed: u32,
-    /// Random number generator
-    rng: rand::rngs::ThreadRng,
+    /// The number of shapes fixed into place
+    shape_count: u32,
+    /// True if the last successful move of the current shape was a rotation, see also TSpin
+    last_move_rotate: bool,
+    /// The number of consecutive shapes that completed rows, or -1 if the last shape completed no rows
+    combo: i32,
+    /// True if the last rows were completed with 4 rows or a T-spin, see also AttackTable
+    back_to_back: bool,
+    /// Determines the attack of each shape fixed into place
+    attack_table: AttackTable,
+    /// The total attack sent this game
+    attack: u32,
+    /// Incoming garbage waiting to be pushed onto the bottom of the board, oldest first
+    garbage_queue: VecDeque<Garbage>,
+    /// Random number generator, see also set_seed()
+    rng: StdRng,
+}
+
+impl Default for Tetris {
+    fn default() -> Tetris {
+        Tetris::new()
+    }
 }
 
 impl Tetris {
@@ -156,11 +627,15 @@ impl Tetris {
     pub fn new() -> Tetris {
         Tetris { 
             grid: [[GridCell::default(); ROW_COUNT as usize]; COL_COUNT as usize],
+            row_bits: [0; ROW_COUNT as usize],
             game_over: true,
+            paused: false,
             shape_index: 0,
-            next_shape_index: 0,
+            next_queue: vec![0; PREVIEW_COUNT as usize].into_iter().collect(),
+            rotation: 0,
+            hold_shape_index: -1,
+            can_hold: true,
             shape: SHAPES[0],
-            next_shape: SHAPES[0],
             col: 0,
             row: 0,
             ghost_row: 0,
@@ -169,7 +644,14 @@ impl Tetris {
             score: 0,
             rows_completed: 0,
             rows_completed_level: 0,
-            rng: rand::thread_rng(),
+            shape_count: 0,
+            last_move_rotate: false,
+            combo: -1,
+            back_to_back: false,
+            attack_table: AttackTable::guideline(),
+            attack: 0,
+            garbage_queue: VecDeque::new(),
+            rng: StdRng::from_entropy(),
         }
     }
 
@@ -178,13 +660,34 @@ impl Tetris {
         self.game_over
     }
 
+    /// Returns true while the game is paused
+    pub fn get_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Pauses or resumes the game. While the game is paused apply_input(), hard_drop() and tick() are ignored,
+    /// and callers should stop the timer that invokes tick(). A game that is over can't be paused.
+    pub fn set_paused(&mut self, value: bool) {
+        self.paused = value && !self.game_over;
+    }
+
     /// Gets the GridCell at the specified col and row. See also GridCell.
     pub fn get_grid_cell(&self, col: i32, row: i32) -> GridCell {
-        assert!(col >= 0 && col <= COL_COUNT as i32 - 1);
-        assert!(row >= 0 && row <= ROW_COUNT as i32 - 1);
+        assert!(col >= 0 && col < COL_COUNT as i32);
+        assert!(row >= 0 && row < ROW_COUNT as i32);
         self.grid[col as usize][row as usize]
     }
 
+    /// Returns the Fixed cells of each row as a bit mask, where bit n is set if column n is filled
+    pub fn get_row_bits(&self) -> &[u16; ROW_COUNT as usize] {
+        &self.row_bits
+    }
+
+    /// Measures the Fixed cells of the game board, see also BoardAnalysis
+    pub fn analyze_board(&self) -> BoardAnalysis {
+        BoardAnalysis::new(&self.row_bits)
+    }
+
     /// Returns the column position of the current shape. Note each Point.x value of the shape
     /// can be added to this column value to determine the actual position of the Point.
     pub fn get_col(&self) -> i32 {
@@ -202,6 +705,7 @@ impl Tetris {
                 // move the current shape, and clear its old position before moving
                 self.move_shape(col, use_row, true);
                 self.col = col;
+                self.last_move_rotate = false;
             }
             result
         } else {
@@ -226,6 +730,7 @@ impl Tetris {
                 // move the current shape, and clear its old position before moving
                 self.move_shape(use_col, row, true);
                 self.row = row;
+                self.last_move_rotate = false;
             }
             result
         } else {
@@ -237,6 +742,39 @@ impl Tetris {
         self.score
     }
 
+    pub fn get_rows_completed(&self) -> u32 {
+        self.rows_completed
+    }
+
+    /// Returns the number of shapes fixed into place this game
+    pub fn get_shape_count(&self) -> u32 {
+        self.shape_count
+    }
+
+    /// Returns the number of consecutive shapes that completed rows, less one, or -1 if the last
+    /// shape completed no rows
+    pub fn get_combo(&self) -> i32 {
+        self.combo
+    }
+
+    /// Returns true if the last rows were completed with 4 rows or a T-spin, see also AttackTable
+    pub fn get_back_to_back(&self) -> bool {
+        self.back_to_back
+    }
+
+    /// Returns the total attack sent this game, see also AttackTable
+    pub fn get_attack(&self) -> u32 {
+        self.attack
+    }
+
+    pub fn get_attack_table(&self) -> &AttackTable {
+        &self.attack_table
+    }
+
+    pub fn set_attack_table(&mut self, value: AttackTable) {
+        self.attack_table = value;
+    }
+
     pub fn get_starting_level(&self) -> u32 {
         self.starting_level
     }
@@ -250,70 +788,241 @@ impl Tetris {
     }
 
     pub fn get_next_shape(&self) -> [Point; POINT_COUNT as usize] {
-        self.next_shape
+        SHAPES[self.get_next_shape_index() as usize]
     }
 
     pub fn get_next_shape_index(&self) -> i32 {
-        self.next_shape_index
+        self.next_queue[0]
+    }
+
+    /// Returns the shape indices of the next PREVIEW_COUNT shapes, starting with the next shape
+    pub fn get_next_queue(&self) -> &VecDeque<i32> {
+        &self.next_queue
+    }
+
+    pub fn get_shape_index(&self) -> i32 {
+        self.shape_index
+    }
+
+    /// Returns the number of clockwise rotations of the current shape from its starting position
+    pub fn get_rotation(&self) -> u8 {
+        self.rotation
+    }
+
+    /// Returns the held shape index into the SHAPES const, or -1 if no shape is held
+    pub fn get_hold_shape_index(&self) -> i32 {
+        self.hold_shape_index
+    }
+
+    /// Returns false if the current shape was already held, see also hold()
+    pub fn get_can_hold(&self) -> bool {
+        self.can_hold
+    }
+
+    /// Use hold() when the player presses a key to hold the current shape. The current shape is
+    /// swapped with the held shape, or replaced with the next shape if no shape was held. The
+    /// shape can't be held again until it is fixed into place.
+    pub fn hold(&mut self) -> bool {
+        if !self.game_over && self.can_hold {
+            self.clear_shape();
+            let hold_shape_index = self.hold_shape_index;
+            self.hold_shape_index = self.shape_index;
+            let result = if hold_shape_index >= 0 {
+                self.spawn_shape(hold_shape_index)
+            } else {
+                self.new_shape()
+            };
+            self.can_hold = false;
+            if !result {
+                self.end_game();
+            }
+            result
+        } else {
+            false
+        }
+    }
+
+    /// Returns every position where the current shape can be fixed into place using the moves
+    /// available to the player: moving left and right, or all the way to the wall by holding the
+    /// key past DAS, rotating either way with wall kicks, turning
+    /// around with the kicks of KICKS_180, and soft drops.
+    /// If include_hold is true, the positions of the shape swapped in by hold() are included too.
+    /// Each Placement has the shortest path of inputs that reaches it, and positions covering
+    /// the same cells are only returned once.
+    pub fn get_placements(&self, include_hold: bool) -> Vec<Placement> {
+        if self.game_over {
+            return Vec::new();
+        }
+        let mut result = self.shape_placements(self.shape_index, self.shape, self.col, self.row, self.rotation, &[]);
+        if include_hold && self.can_hold {
+            let shape_index = if self.hold_shape_index >= 0 {
+                self.hold_shape_index
+            } else {
+                self.get_next_shape_index()
+            };
+            let shape = SHAPES[shape_index as usize];
+            let (col, row) = (COL_COUNT as i32 / 2, 0);
+            if self.valid_location(shape, col, row, true) {
+                result.extend(self.shape_placements(shape_index, shape, col, row, 0, &[Input::Hold]));
+            }
+        }
+        result
     }
 
     /// Use rotate() when the player presses a key to rotate the current shape.
     pub fn rotate(&mut self, clockwise: bool) -> bool {
         if !self.game_over {
-            // rotate a copy of the current shape
-            let mut shape = self.shape;
-            // there is no need to rotate the square shape as it is symmetrical
-            if self.shape_index != SQUARE_SHAPE_INDEX {
-                self.rotate_shape(clockwise, &mut shape);
-            }
-            // if this new shape is in a valid position (not checking sides because we can wall kick)...
-            let mut result: bool = self.valid_location(shape, self.col, self.row, false);
-            if result {
-                // perform wall kick if necessary
-                let col = self.wall_kick(shape);
-                result = col >= 0;
-                if result {
+            match self.rotated(self.shape_index, self.shape, self.col, self.row, clockwise) {
+                Some((shape, col)) => {
                     // ...then remove the current shape from the board
                     self.clear_shape(); // normally move_shape will take care of this, however, the shape itself is changing (not just position)
                     // ...then assign the copy to the current shape
                     self.shape = shape;
                     self.col = col;
+                    if self.shape_index != SQUARE_SHAPE_INDEX {
+                        self.rotation = (self.rotation + if clockwise { 1 } else { 3 }) % 4;
+                    }
                     let use_row = self.row;
                     // now place the current shape back onto the board
                     self.move_shape(col, use_row, false);
-                }
+                    self.last_move_rotate = true;
+                    true
+                },
+                None => false,
+            }
+        } else {
+            false
+        }
+    }
+
+    /// Use rotate_180() when the player presses a key to turn the current shape around. Rather than
+    /// the wall kicks of rotate(), the shape is kicked by the offsets of KICKS_180.
+    pub fn rotate_180(&mut self) -> bool {
+        if !self.game_over {
+            match self.rotated_180(self.shape_index, self.shape, self.col, self.row) {
+                Some((shape, col, row)) => {
+                    self.clear_shape();
+                    self.shape = shape;
+                    self.col = col;
+                    self.row = row;
+                    if self.shape_index != SQUARE_SHAPE_INDEX {
+                        self.rotation = (self.rotation + 2) % 4;
+                    }
+                    self.move_shape(col, row, false);
+                    self.last_move_rotate = true;
+                    true
+                },
+                None => false,
             }
-            result
         } else {
             false
         }
     }
 
+    /// Seeds the random number generator that determines the shapes. Invoke set_seed() before
+    /// start_game() so that the same seed and inputs always play the same game.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// Drops the current shape as far as it can go, then fixes it into place.
+    /// Returns the LockResult, see also tick().
+    pub fn hard_drop(&mut self) -> Option<LockResult> {
+        if self.paused {
+            return None;
+        }
+        let mut row: i32 = self.row + 1;
+        while self.set_row(row) {
+            row += 1;
+        }
+        self.tick()
+    }
+
+    /// Applies an Input to the game, returning a LockResult if the current shape was fixed into place
+    pub fn apply_input(&mut self, input: Input) -> Option<LockResult> {
+        if self.paused {
+            return None;
+        }
+        match input {
+            Input::Left => {
+                let col = self.col - 1;
+                self.set_col(col);
+                None
+            },
+            Input::Right => {
+                let col = self.col + 1;
+                self.set_col(col);
+                None
+            },
+            Input::RotateClockwise => {
+                self.rotate(true);
+                None
+            },
+            Input::RotateCounterClockwise => {
+                self.rotate(false);
+                None
+            },
+            Input::Rotate180 => {
+                self.rotate_180();
+                None
+            },
+            Input::SoftDrop => {
+                let row = self.row + 1;
+                self.set_row(row);
+                None
+            },
+            Input::HardDrop => self.hard_drop(),
+            Input::Hold => {
+                self.hold();
+                None
+            },
+            Input::Tick => self.tick(),
+        }
+    }
+
     /// Starts a new game by clearing the game board, and resetting the level, score etc.
     pub fn start_game(&mut self) {
         if self.game_over {
             self.game_over = false;
+            self.paused = false;
             self.level = self.starting_level;
             self.score = 0;
             self.rows_completed = 0;
             self.rows_completed_level = 0;
+            self.shape_count = 0;
+            self.combo = -1;
+            self.back_to_back = false;
+            self.hold_shape_index = -1;
+            self.attack = 0;
+            self.garbage_queue.clear();
             self.clear_grid();
-            // next shape is a random shape
-            self.next_shape_index = self.rng.gen_range(0, SHAPE_COUNT as i32);
-            self.next_shape = SHAPES[self.next_shape_index as usize];
+            // the upcoming shapes are random shapes
+            self.next_queue.clear();
+            for _ in 0..PREVIEW_COUNT {
+                let shape_index = self.rng.gen_range(0, SHAPE_COUNT as i32);
+                self.next_queue.push_back(shape_index);
+            }
             // add a new shape on the board
             self.new_shape();
         }
     }
 
     /// Advances the state of the game board. Invoke tick() at a time interval related to the current level.
-    pub fn tick(&mut self) {
-        if !self.game_over {
+    /// Returns a LockResult when the current shape could not advance and was fixed into place.
+    pub fn tick(&mut self) -> Option<LockResult> {
+        if !self.game_over && !self.paused {
             let new_row = self.row + 1;
             // if we can't move the shape to a new row...
             if !self.set_row(new_row) {
                 // ...then fix the shape into place
+                let mut cells = self.shape;
+                for point in cells.iter_mut() {
+                    *point = self.transform_point(self.col, self.row, *point);
+                }
                 self.shape_to_grid();
+                self.shape_count += 1;
+                // T-spins depend on the cells around the shape before any rows are removed
+                let t_spin = self.t_spin();
                 // ...then determine if we completed any rows
                 let rows = self.complete_rows();
                 // calculate new score
@@ -324,18 +1033,25 @@ impl Tetris {
                     4 => 1200,
                     _ => 0,
                 };
-                self.score += score_factor as u32 * (self.level + 1);
+                let score = score_factor as u32 * (self.level + 1);
+                self.score += score;
                 // determine if we should start a new level
                 if self.rows_completed_level > ROWS_PER_LEVEL {
                     self.rows_completed_level = 0;
                     self.level += 1;
                 }
+                let result = self.lock_result(rows, t_spin, score, cells);
+                // garbage only arrives when the shape didn't complete any rows
+                if rows == 0 && !self.push_garbage_queue() {
+                    self.end_game();
                 // ...now place a new shape onto the board
-                if !self.new_shape() {
+                } else if !self.new_shape() {
                     self.end_game();
                 }
+                return Some(result);
             }
         }
+        None
     }
 
     /// Calculates the time granted between calls to tick(). As the level increases, the amount
@@ -357,16 +1073,58 @@ impl Tetris {
         const ENDING_TIME: f32 = 0.25f32;
 
         if self.level < 10 {
-            (STARTING_SLOPE * (self.level - 0) as f32) + STARTING_TIME
+            (STARTING_SLOPE * self.level as f32) + STARTING_TIME
         } else {
             (ENDING_SLOPE * (self.level - 10) as f32) + ENDING_TIME
         }
     }
 
+    /// Queues rows of garbage sent to this game, e.g. when an opponent completes rows in a versus game.
+    /// The queued garbage is pushed onto the bottom of the board the next time a shape is fixed into
+    /// place without completing any rows, shifting the rest of the board up.
+    pub fn queue_garbage(&mut self, rows: u32, hole_col: i32) {
+        assert!(hole_col >= 0 && hole_col < COL_COUNT as i32);
+        if !self.game_over && rows > 0 {
+            self.garbage_queue.push_back(Garbage { rows, hole_col });
+        }
+    }
+
+    /// Cancels up to the given number of queued garbage rows, oldest first. This lets rows the
+    /// player would send to an opponent first cancel their own incoming garbage.
+    /// Returns the number of rows left over after cancelling.
+    pub fn cancel_garbage(&mut self, rows: u32) -> u32 {
+        let mut result = rows;
+        while result > 0 {
+            let front_rows = match self.garbage_queue.front() {
+                Some(garbage) => garbage.rows,
+                None => break,
+            };
+            if front_rows > result {
+                self.garbage_queue[0].rows -= result;
+                result = 0;
+            } else {
+                self.garbage_queue.pop_front();
+                result -= front_rows;
+            }
+        }
+        result
+    }
+
+    /// Returns the total number of garbage rows waiting to be pushed onto the board
+    pub fn get_pending_garbage(&self) -> u32 {
+        self.garbage_queue.iter().map(|garbage| garbage.rows).sum()
+    }
+
+    /// Returns the queue of incoming garbage, oldest first
+    pub fn get_garbage_queue(&self) -> &VecDeque<Garbage> {
+        &self.garbage_queue
+    }
+
     /// Ends the game. However, the current state of the game is preserved (e.g. not clearing the game board)
     /// because rendering code might still display the board
     pub fn end_game(&mut self) {
         self.game_over = true;
+        self.paused = false;
     }
 
     /* Private methods */
@@ -374,15 +1132,25 @@ impl Tetris {
     /// Clear the entire game board
     fn clear_grid(&mut self) {
         self.grid = [[GridCell::default(); ROW_COUNT as usize]; COL_COUNT as usize];
+        self.row_bits = [0; ROW_COUNT as usize];
     }
 
     /// Add a new shape on the board.
     fn new_shape(&mut self) -> bool {
+        let shape_index = self.next_queue.pop_front().unwrap();
+        let next_shape_index = self.rng.gen_range(0, SHAPE_COUNT as i32);
+        self.next_queue.push_back(next_shape_index);
+        self.can_hold = true;
+        self.spawn_shape(shape_index)
+    }
+
+    /// Place the given shape at the top of the board as the current shape
+    fn spawn_shape(&mut self, shape_index: i32) -> bool {
         self.row = 0;
         self.col = COL_COUNT as i32 / 2;
-        self.shape_index = self.next_shape_index;
-        self.next_shape_index = self.rng.gen_range(0, SHAPE_COUNT as i32);
-        self.next_shape = SHAPES[self.next_shape_index as usize];
+        self.rotation = 0;
+        self.last_move_rotate = false;
+        self.shape_index = shape_index;
         self.shape = SHAPES[self.shape_index as usize];
         let result: bool = self.valid_location(self.shape, self.col, self.row, true);
         if result {
@@ -393,6 +1161,121 @@ impl Tetris {
         result
     }
 
+    /// Search every position a shape can reach from its current position, see also get_placements()
+    fn shape_placements(&self, shape_index: i32, shape: [Point; POINT_COUNT as usize], col: i32, row: i32,
+        rotation: u8, path_prefix: &[Input]) -> Vec<Placement> {
+        let mut result: Vec<Placement> = Vec::new();
+        // each state is a position and rotation of the shape, visited in order of the inputs needed
+        // to reach it. visited has a bit for each rotation at each position.
+        let mut visited = [[0u8; ROW_COUNT as usize + 2 * SEARCH_MARGIN as usize]; COL_COUNT as usize + 2 * SEARCH_MARGIN as usize];
+        let mut visit = |col: i32, row: i32, rotation: u8| {
+            let cell = &mut visited[(col + SEARCH_MARGIN) as usize][(row + SEARCH_MARGIN) as usize];
+            let result = *cell & (1 << rotation) == 0;
+            *cell |= 1 << rotation;
+            result
+        };
+        let mut states: Vec<SearchState> = Vec::new();
+        // the same cells reached by different rotations only count once, except a T shape rotated into
+        // place may score a T-spin
+        let mut placed: Vec<([Point; POINT_COUNT as usize], bool)> = Vec::new();
+        visit(col, row, rotation);
+        states.push(SearchState { col, row, rotation, shape, parent: None, input: Input::Tick, count: 0 });
+        let mut index = 0;
+        while index < states.len() {
+            let state = states[index];
+            let SearchState { col, row, rotation, shape, .. } = state;
+            let drop_row = self.drop_row(shape, col, row);
+            let rotated_last = shape_index == T_SHAPE_INDEX && drop_row == row &&
+                (state.input == Input::RotateClockwise || state.input == Input::RotateCounterClockwise ||
+                    state.input == Input::Rotate180);
+            let mut cells = shape;
+            for point in cells.iter_mut() {
+                *point = self.transform_point(col, drop_row, *point);
+            }
+            cells.sort();
+            if !placed.contains(&(cells, rotated_last)) {
+                placed.push((cells, rotated_last));
+                let (path, keys) = self.search_path(&states, index, path_prefix);
+                result.push(Placement { shape_index, shape, rotation, col, row: drop_row, path, keys });
+            }
+
+            let mut moves: Vec<SearchState> = Vec::new();
+            let next = SearchState { parent: Some(index), count: 1, ..state };
+            for &(input, offset) in [(Input::Left, -1), (Input::Right, 1)].iter() {
+                if self.valid_location(shape, col + offset, row, true) {
+                    moves.push(SearchState { col: col + offset, input, ..next });
+                }
+            }
+            // holding left or right past DAS moves the shape all the way to the wall, which is
+            // also a single key press
+            for &(input, offset) in [(Input::Left, -1), (Input::Right, 1)].iter() {
+                let mut wall_col = col;
+                while self.valid_location(shape, wall_col + offset, row, true) {
+                    wall_col += offset;
+                }
+                if (wall_col - col).abs() > 1 {
+                    moves.push(SearchState { col: wall_col, input, count: (wall_col - col).unsigned_abs() as u8, ..next });
+                }
+            }
+            if shape_index != SQUARE_SHAPE_INDEX {
+                for &(input, clockwise) in [(Input::RotateClockwise, true), (Input::RotateCounterClockwise, false)].iter() {
+                    if let Some((rotated_shape, rotated_col)) = self.rotated(shape_index, shape, col, row, clockwise) {
+                        let rotated_rotation = (rotation + if clockwise { 1 } else { 3 }) % 4;
+                        moves.push(SearchState { col: rotated_col, rotation: rotated_rotation, shape: rotated_shape, input, ..next });
+                    }
+                }
+                if let Some((rotated_shape, rotated_col, rotated_row)) = self.rotated_180(shape_index, shape, col, row) {
+                    moves.push(SearchState { col: rotated_col, row: rotated_row, rotation: (rotation + 2) % 4, shape: rotated_shape,
+                        input: Input::Rotate180, ..next });
+                }
+            }
+            // holding soft drop moves the shape all the way down, which is a single key press, and
+            // tapping it moves one row, so shapes can stop partway down and slide under overhangs
+            if drop_row > row {
+                moves.push(SearchState { row: drop_row, input: Input::SoftDrop, count: (drop_row - row) as u8, ..next });
+            }
+            if drop_row > row + 1 {
+                moves.push(SearchState { row: row + 1, input: Input::SoftDrop, ..next });
+            }
+            for next_state in moves {
+                if visit(next_state.col, next_state.row, next_state.rotation) {
+                    states.push(next_state);
+                }
+            }
+            index += 1;
+        }
+        result
+    }
+
+    /// Returns the inputs that reach a state of shape_placements(), followed by a hard drop, and
+    /// the keys pressed to follow them, see also Placement::keys
+    fn search_path(&self, states: &[SearchState], index: usize, path_prefix: &[Input]) -> (Vec<Input>, Vec<(Input, u8)>) {
+        let mut inputs: Vec<(Input, u8)> = Vec::new();
+        let mut state = &states[index];
+        while let Some(parent) = state.parent {
+            inputs.push((state.input, state.count));
+            state = &states[parent];
+        }
+        inputs.reverse();
+        inputs.push((Input::HardDrop, 1));
+        let mut result = path_prefix.to_vec();
+        for &(input, count) in inputs.iter() {
+            result.extend(std::iter::repeat_n(input, count as usize));
+        }
+        // each state was reached with a single key
+        let keys = path_prefix.iter().map(|input| (*input, 1)).chain(inputs).collect();
+        (result, keys)
+    }
+
+    /// Returns the lowest row a shape can drop to from the given position
+    fn drop_row(&self, shape: [Point; POINT_COUNT as usize], col: i32, row: i32) -> i32 {
+        let mut result = row;
+        while self.valid_location(shape, col, result + 1, true) {
+            result += 1;
+        }
+        result
+    }
+
     /// Compute the actual point on the grid based on a shape point and row, col values
     /// The resulting point may be out of bounds
     fn transform_point(&self, col: i32, row: i32, point: Point) -> Point {
@@ -415,7 +1298,7 @@ impl Tetris {
                 grid_point.y >= ROW_COUNT as i16 ||
                 (self.point_in_bounds(col, row, *point) && 
                     // ok to cast to unsigned after checking in bounds...
-                    self.grid[grid_point.x as usize][grid_point.y as usize].cell_type == GridCellType::Fixed) {
+                    self.row_bits[grid_point.y as usize] & (1 << grid_point.x) != 0) {
                 result = false;
                 break;
             }
@@ -449,7 +1332,7 @@ impl Tetris {
             assert!(grid_cell.cell_type == GridCellType::Void ||
                 grid_cell.cell_type == GridCellType::Ghost);
             grid_cell.cell_type = GridCellType::Shape;
-            grid_cell.shape_index = use_shape_index as i32;
+            grid_cell.shape_index = use_shape_index;
         });
         // determine the row where ghost shape is placed
         self.ghost_row = row;
@@ -510,6 +1393,12 @@ impl Tetris {
             assert!(grid_cell.cell_type == GridCellType::Shape);
             grid_cell.cell_type = GridCellType::Fixed;
         });
+        for point in use_shape.iter() {
+            if self.point_in_bounds(use_col, use_row, *point) {
+                let grid_point = self.transform_point(use_col, use_row, *point);
+                self.row_bits[grid_point.y as usize] |= 1 << grid_point.x;
+            }
+        }
     }
 
     /// Determine if any rows have any gaps, and if they do not, then remove those
@@ -520,23 +1409,19 @@ impl Tetris {
         let mut row: i32 = ROW_COUNT as i32 - 1;
         while row >= 0 {
             let row_index: usize = row as usize;
-            // look for any void spots on this row
-            let found_void = (0..COL_COUNT as usize).any(|c| self.grid[c][row_index].cell_type == GridCellType::Void);
-            if !found_void {
+            // the row is complete when every column is filled
+            if self.row_bits[row_index] == FULL_ROW {
                 result += 1;
                 self.rows_completed_level += 1;
                 self.rows_completed += 1;
 
                 // bring all rows above row down one...
-                for col in 0..COL_COUNT as usize {
-                    // iterate in reverse starting from row - 1, back to 0...
-                    for temp_row in (0..row_index).rev() {
-                        self.grid[col][temp_row + 1].shape_index = self.grid[col][temp_row].shape_index;
-                        self.grid[col][temp_row + 1].cell_type = self.grid[col][temp_row].cell_type;
-                    }
+                self.row_bits.copy_within(0..row_index, 1);
+                self.row_bits[0] = 0;
+                for column in self.grid.iter_mut() {
+                    column.copy_within(0..row_index, 1);
                     // clear top row
-                    self.grid[col][0].shape_index = -1;
-                    self.grid[col][0].cell_type = GridCellType::Void;
+                    column[0] = GridCell::default();
                 }
             } else {
                 row -= 1;
@@ -545,6 +1430,95 @@ impl Tetris {
         result
     }
 
+    /// Determine the kind of T-spin for the current shape after it has been fixed into place
+    fn t_spin(&self) -> TSpin {
+        if self.shape_index != T_SHAPE_INDEX || !self.last_move_rotate {
+            return TSpin::None;
+        }
+        // the point of the T is the only point, other than the center, without an opposite point
+        let point = match self.shape.iter().find(|point| (point.x != 0 || point.y != 0) &&
+            !self.shape.iter().any(|other| other.x == -point.x && other.y == -point.y)) {
+            Some(point) => *point,
+            None => return TSpin::None,
+        };
+        // a corner is blocked if it is a wall, the floor or a Fixed cell
+        let blocked = |x: i16, y: i16| {
+            let grid_point = self.transform_point(self.col, self.row, Point { x, y });
+            grid_point.x < 0 || grid_point.x >= COL_COUNT as i16 || grid_point.y >= ROW_COUNT as i16 ||
+                (grid_point.y >= 0 && self.row_bits[grid_point.y as usize] & (1 << grid_point.x) != 0)
+        };
+        // the two corners on the pointing side of the T, then the two corners behind it
+        let front = [blocked(point.x - point.y, point.y + point.x), blocked(point.x + point.y, point.y - point.x)];
+        let back = [blocked(-point.x - point.y, -point.y + point.x), blocked(-point.x + point.y, -point.y - point.x)];
+        let count = front.iter().chain(back.iter()).filter(|blocked| **blocked).count();
+        if count < 3 {
+            TSpin::None
+        } else if front[0] && front[1] {
+            TSpin::Full
+        } else {
+            TSpin::Mini
+        }
+    }
+
+    /// Update the combo, back to back and attack state after a shape has been fixed into place
+    fn lock_result(&mut self, rows: u8, t_spin: TSpin, score: u32, cells: [Point; POINT_COUNT as usize]) -> LockResult {
+        let mut back_to_back = false;
+        if rows > 0 {
+            self.combo += 1;
+            let difficult = rows >= 4 || t_spin != TSpin::None;
+            back_to_back = difficult && self.back_to_back;
+            self.back_to_back = difficult;
+        } else {
+            self.combo = -1;
+        }
+        let combo = std::cmp::max(self.combo, 0) as u32;
+        let perfect_clear = rows > 0 && self.row_bits.iter().all(|bits| *bits == 0);
+        let attack = self.attack_table.attack(rows, t_spin, combo, back_to_back, perfect_clear);
+        self.attack += attack;
+        LockResult { rows, t_spin, combo, back_to_back, perfect_clear, score, attack, cells }
+    }
+
+    /// Push every queued garbage onto the board, oldest first. Returns false if Fixed cells were
+    /// pushed off the top of the board.
+    fn push_garbage_queue(&mut self) -> bool {
+        let mut result = true;
+        while let Some(garbage) = self.garbage_queue.pop_front() {
+            result = self.push_garbage_rows(garbage) && result;
+        }
+        result
+    }
+
+    /// Push all rows up to make room for garbage rows at the bottom of the board. Each garbage row
+    /// is filled except for the hole column. Returns false if Fixed cells were pushed off the top
+    /// of the board.
+    fn push_garbage_rows(&mut self, garbage: Garbage) -> bool {
+        let count = std::cmp::min(garbage.rows, ROW_COUNT as u32) as usize;
+        // the top rows are about to be pushed out of the grid
+        let result = self.row_bits[..count].iter().all(|bits| *bits == 0);
+        self.row_bits.copy_within(count.., 0);
+        for bits in self.row_bits[ROW_COUNT as usize - count..].iter_mut() {
+            *bits = FULL_ROW & !(1 << garbage.hole_col);
+        }
+        for col in 0..COL_COUNT as usize {
+            // bring all rows up by count...
+            for row in count..ROW_COUNT as usize {
+                self.grid[col][row - count] = self.grid[col][row];
+            }
+            // ...then fill the bottom rows with garbage
+            for row in ROW_COUNT as usize - count..ROW_COUNT as usize {
+                self.grid[col][row] = if col as i32 == garbage.hole_col {
+                    GridCell::default()
+                } else {
+                    GridCell {
+                        cell_type: GridCellType::Fixed,
+                        shape_index: GARBAGE_SHAPE_INDEX,
+                    }
+                };
+            }
+        }
+        result
+    }
+
     /// Given a shape, rotate each point of the shape to a new quadrant
     fn rotate_shape(&self, clockwise: bool, shape: &mut [Point; POINT_COUNT as usize]) {
         for point in &mut shape.iter_mut() {
@@ -552,30 +1526,67 @@ impl Tetris {
             if !clockwise {
                 let old_x = point.x;
                 point.x = point.y;
-                point.y = -1 * old_x;
+                point.y = -old_x;
             } else {
                 let old_y = point.y;
                 point.y = point.x;
-                point.x = -1 * old_y;
+                point.x = -old_y;
             }
         }
     }
 
+    /// Given a shape at col, row, determine the rotated shape and its column after any wall kick.
+    /// Returns None if the shape can't be rotated.
+    fn rotated(&self, shape_index: i32, shape: [Point; POINT_COUNT as usize], col: i32, row: i32,
+        clockwise: bool) -> Option<([Point; POINT_COUNT as usize], i32)> {
+        // rotate a copy of the shape
+        let mut shape = shape;
+        // there is no need to rotate the square shape as it is symmetrical
+        if shape_index != SQUARE_SHAPE_INDEX {
+            self.rotate_shape(clockwise, &mut shape);
+        }
+        // if this new shape is in a valid position (not checking sides because we can wall kick)...
+        if self.valid_location(shape, col, row, false) {
+            // perform wall kick if necessary
+            let kick_col = self.wall_kick(shape_index, shape, col, row);
+            if kick_col >= 0 {
+                return Some((shape, kick_col));
+            }
+        }
+        None
+    }
+
+    /// Given a shape at col, row, determine the shape turned around and its column and row after
+    /// the first kick of KICKS_180 that fits. Returns None if the shape can't be turned around.
+    fn rotated_180(&self, shape_index: i32, shape: [Point; POINT_COUNT as usize], col: i32, row: i32)
+        -> Option<([Point; POINT_COUNT as usize], i32, i32)> {
+        let mut shape = shape;
+        if shape_index != SQUARE_SHAPE_INDEX {
+            self.rotate_shape(true, &mut shape);
+            self.rotate_shape(true, &mut shape);
+        }
+        KICKS_180.iter()
+            .map(|&(col_offset, row_offset)| (col + col_offset, row + row_offset))
+            .find(|&(col, row)| col >= 0 && col < COL_COUNT as i32 && row >= 0 &&
+                self.valid_location(shape, col, row, true))
+            .map(|(col, row)| (shape, col, row))
+    }
+
     /// Calculate a new column if any of the points of the supplied shape are out of bounds to the left or right
-    /// The resulting col position will be offset from the current self.col if a valid location is found,
+    /// The resulting col position will be offset from col if a valid location is found,
     /// otherwise -1 is returned
-    fn wall_kick(&mut self, shape: [Point; POINT_COUNT as usize]) -> i32 {
+    fn wall_kick(&self, shape_index: i32, shape: [Point; POINT_COUNT as usize], col: i32, row: i32) -> i32 {
         // square piece doesn't rotate, so no need to wall kick
-        if self.shape_index != SQUARE_SHAPE_INDEX {
+        if shape_index != SQUARE_SHAPE_INDEX {
             let mut result: i32 = -1;
             // if on left side of the board, then kick to right, e.g. +1, else -1
-            let increment = if self.col < COL_COUNT as i32 / 2 {
+            let increment = if col < COL_COUNT as i32 / 2 {
                 1
             } else {
                 -1
             };
             for point in shape.iter() {
-                let mut kick_col: i32 = self.col;
+                let mut kick_col: i32 = col;
                 // loop until we've shifted kick_col in bounds for this point's x value
                 // after loop, kick_col will be in bounds but not necessarily in valid location
                 loop {
@@ -589,14 +1600,543 @@ impl Tetris {
                 }
                 // ensure kick_col is a valid location
                 // e.g. we may have kicked into a place where there are Fixed cells
-                if self.valid_location(shape, kick_col, self.row, true) {
+                if self.valid_location(shape, kick_col, row, true) {
                     result = kick_col;
                     break;
                 }
             }
             result
         } else {
-            self.col
+            col
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+impl Tetris {
+    /// Starts a game for tests with the given rows at the bottom of the board, where 'X' is a
+    /// Fixed cell, and the given held shape or -1. The first of shape_indices is the current
+    /// shape and the rest come next, followed by the random shapes of a fixed seed.
+    pub fn with_board(rows: &[&str], hold_shape_index: i32, shape_indices: &[i32]) -> Tetris {
+        let mut tetris = Tetris::new();
+        tetris.set_seed(1);
+        tetris.start_game();
+        tetris.clear_shape();
+        let top = ROW_COUNT as usize - rows.len();
+        for (y, text) in rows.iter().enumerate() {
+            for (x, character) in text.chars().enumerate() {
+                if character == 'X' {
+                    tetris.grid[x][top + y] = GridCell { cell_type: GridCellType::Fixed, shape_index: GARBAGE_SHAPE_INDEX };
+                    tetris.row_bits[top + y] |= 1 << x;
+                }
+            }
+        }
+        let (shape_index, next) = shape_indices.split_first().unwrap();
+        let count = next.len().max(PREVIEW_COUNT as usize);
+        tetris.next_queue = next.iter().chain(tetris.next_queue.iter()).cloned().take(count).collect();
+        tetris.hold_shape_index = hold_shape_index;
+        assert!(tetris.spawn_shape(*shape_index));
+        tetris
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Starts a game with the given rows at the bottom of the board, where 'X' is a Fixed cell,
+    /// and the given shape as the current shape
+    fn with_board(shape_index: i32, rows: &[&str]) -> Tetris {
+        Tetris::with_board(rows, -1, &[shape_index])
+    }
+
+    /// Returns the cells of a placement, sorted
+    fn sorted_cells(placement: &Placement) -> Vec<(i16, i16)> {
+        let mut result: Vec<(i16, i16)> = placement.get_cells().iter().map(|point| (point.x, point.y)).collect();
+        result.sort();
+        result
+    }
+
+    /// Follows the path of a placement, returning the LockResult of its hard drop
+    fn play_path(tetris: &mut Tetris, placement: &Placement) -> LockResult {
+        let (last, inputs) = placement.path.split_last().unwrap();
+        for input in inputs {
+            assert!(tetris.apply_input(*input).is_none());
+        }
+        assert_eq!(*last, Input::HardDrop);
+        tetris.apply_input(*last).unwrap()
+    }
+
+    #[test]
+    fn every_column_and_rotation_is_reachable_on_an_empty_board() {
+        // each orientation that covers different cells, times the columns it fits in
+        for &(shape_index, count) in [(T_SHAPE_INDEX, 34), (SQUARE_SHAPE_INDEX, 9), (2, 17), (3, 17), (4, 34), (5, 34), (6, 17)].iter() {
+            let tetris = with_board(shape_index, &[]);
+            let placements = tetris.get_placements(false);
+            assert_eq!(placements.len(), count, "shape {}", shape_index);
+            for placement in placements.iter() {
+                assert_eq!(*placement.path.last().unwrap(), Input::HardDrop);
+                assert!(sorted_cells(placement).iter().any(|&(_, y)| y == ROW_COUNT as i16 - 1));
+            }
+        }
+    }
+
+    #[test]
+    fn placement_paths_reach_their_cells() {
+        let rows = ["X.........", "XX...X..XX", "XXX.XXX.XX", "XXXX.XXXXX"];
+        for shape_index in 0..SHAPE_COUNT as i32 {
+            let tetris = with_board(shape_index, &rows);
+            let placements = tetris.get_placements(true);
+            assert!(!placements.is_empty());
+            for placement in placements.iter() {
+                let mut game = tetris.clone();
+                let result = play_path(&mut game, placement);
+                let mut cells: Vec<(i16, i16)> = result.cells.iter().map(|point| (point.x, point.y)).collect();
+                cells.sort();
+                assert_eq!(cells, sorted_cells(placement), "path {:?}", placement.path);
+                assert_eq!(placement.path.len(), placement.keys.iter().map(|(_, count)| *count as usize).sum::<usize>());
+            }
+        }
+    }
+
+    #[test]
+    fn shapes_tuck_under_overhangs() {
+        let tetris = with_board(6, &["XXXXXX....", ".........."]);
+        let placements = tetris.get_placements(false);
+        let tuck = placements.iter()
+            .find(|placement| sorted_cells(placement) == vec![(0, 21), (1, 21), (2, 21), (3, 21)])
+            .expect("the I shape should slide under the overhang");
+        assert!(tuck.path.contains(&Input::SoftDrop));
+        let soft_drop = tuck.path.iter().position(|input| *input == Input::SoftDrop).unwrap();
+        assert!(tuck.path[soft_drop..].contains(&Input::Left));
+        // a straight drop can't get there
+        let mut dropped = tetris.clone();
+        dropped.set_col(1);
+        dropped.hard_drop();
+        assert_eq!(dropped.get_row_bits()[21] & 0b1111, 0);
+    }
+
+    #[test]
+    fn shapes_tuck_into_cavities_partway_down() {
+        let rows = ["XXXXXXXX..", "XXXXXXXX..", "XXXXXX....", "XXXXXX....",
+            "XXXXXXXX..", "XXXXXXXX..", "XXXXXXXX..", "XXXXXXXX.."];
+        let tetris = with_board(SQUARE_SHAPE_INDEX, &rows);
+        let placements = tetris.get_placements(false);
+        let cavity = placements.iter()
+            .find(|placement| sorted_cells(placement) == vec![(6, 16), (6, 17), (7, 16), (7, 17)])
+            .expect("the O shape should stop halfway down the well and slide into the cavity");
+        let mut game = tetris.clone();
+        play_path(&mut game, cavity);
+        assert_eq!(game.get_row_bits()[16], FULL_ROW & !0b11_0000_0000);
+        // the bottom of the well is still reachable with a single soft drop
+        let bottom = placements.iter()
+            .find(|placement| sorted_cells(placement) == vec![(8, 20), (8, 21), (9, 20), (9, 21)])
+            .unwrap();
+        assert!(bottom.get_input_count() <= 2);
+    }
+
+    #[test]
+    fn holding_left_or_right_to_the_wall_is_one_key() {
+        let tetris = with_board(SQUARE_SHAPE_INDEX, &[]);
+        let placements = tetris.get_placements(false);
+        let left_wall = placements.iter().find(|placement| sorted_cells(placement)[0].0 == 0).unwrap();
+        assert_eq!(left_wall.keys.len(), 2);
+        assert_eq!(left_wall.keys[0].0, Input::Left);
+        assert!(left_wall.keys[0].1 > 1);
+        let next_to_wall = placements.iter().find(|placement| sorted_cells(placement)[0].0 == 1).unwrap();
+        assert!(next_to_wall.get_input_count() <= 3);
+    }
+
+    #[test]
+    fn turning_around_is_one_key() {
+        let tetris = with_board(T_SHAPE_INDEX, &[]);
+        let placements = tetris.get_placements(false);
+        let turned = placements.iter().find(|placement| placement.rotation == 2 && placement.col == tetris.get_col()).unwrap();
+        assert_eq!(turned.keys, vec![(Input::Rotate180, 1), (Input::HardDrop, 1)]);
+    }
+
+    #[test]
+    fn t_spin_double_is_found_and_scored() {
+        let rows = ["XXXX......", "XXX...XXXX", "XXXX.XXXXX"];
+        let tetris = with_board(T_SHAPE_INDEX, &rows);
+        let placements = tetris.get_placements(false);
+        let slot = vec![(3, 20), (4, 20), (4, 21), (5, 20)];
+        let spins: Vec<&Placement> = placements.iter().filter(|placement| sorted_cells(placement) == slot).collect();
+        assert!(!spins.is_empty(), "the T should rotate into the slot");
+        for placement in spins {
+            let last_move = placement.path[placement.path.len() - 2];
+            assert!(last_move == Input::RotateClockwise || last_move == Input::RotateCounterClockwise ||
+                last_move == Input::Rotate180);
+            let mut game = tetris.clone();
+            let result = play_path(&mut game, placement);
+            assert_eq!(result.t_spin, TSpin::Full);
+            assert_eq!(result.rows, 2);
+        }
+    }
+
+    #[test]
+    fn t_without_three_blocked_corners_is_no_spin() {
+        let tetris = with_board(T_SHAPE_INDEX, &["XXXX.XXXXX"]);
+        let placements = tetris.get_placements(false);
+        // pointing down into the hole, with nothing over the corners above it
+        let placement = placements.iter()
+            .find(|placement| sorted_cells(placement) == vec![(3, 20), (4, 20), (4, 21), (5, 20)])
+            .unwrap();
+        let mut game = tetris.clone();
+        let result = play_path(&mut game, placement);
+        assert_eq!(result.rows, 1);
+        assert_eq!(result.t_spin, TSpin::None);
+    }
+
+    /// Checks that row_bits has a bit set for exactly the Fixed cells of the grid
+    fn assert_row_bits_match(tetris: &Tetris) {
+        for row in 0..ROW_COUNT as usize {
+            let mut bits = 0;
+            for col in 0..COL_COUNT as usize {
+                if tetris.grid[col][row].cell_type == GridCellType::Fixed {
+                    bits |= 1 << col;
+                }
+            }
+            assert_eq!(tetris.row_bits[row], bits, "row {}", row);
+        }
+    }
+
+    #[test]
+    fn row_bits_match_the_grid_after_clears() {
+        let mut tetris = with_board(6, &["XXXXXX....", "XXXXXX....", "XXXXXXX.XX"]);
+        assert_row_bits_match(&tetris);
+        // an I standing in the gap completes one row, leaving the rows above it to fall
+        let placement = tetris.get_placements(false).into_iter()
+            .find(|placement| placement.get_cells().iter().all(|point| point.x == 7))
+            .unwrap();
+        let result = play_path(&mut tetris, &placement);
+        assert_eq!(result.rows, 1);
+        assert_row_bits_match(&tetris);
+        // the row above the cleared row, with the I in the gap, is now the bottom row
+        assert_eq!(tetris.row_bits[21], 0b00_1011_1111);
+    }
+
+    #[test]
+    fn row_bits_match_the_grid_after_garbage() {
+        let mut tetris = with_board(SQUARE_SHAPE_INDEX, &["XXXX......"]);
+        tetris.queue_garbage(3, 8);
+        tetris.hard_drop();
+        assert_row_bits_match(&tetris);
+        assert_eq!(tetris.row_bits[21], FULL_ROW & !(1 << 8));
+        assert_eq!(tetris.row_bits[18] & 0b1111, 0b1111);
+    }
+
+    #[test]
+    fn row_bits_match_the_grid_during_a_game() {
+        let mut tetris = Tetris::new();
+        tetris.set_seed(5);
+        tetris.start_game();
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut rows = 0;
+        let mut garbage_rows = 0;
+        for shape in 0..300 {
+            if tetris.get_game_over() {
+                tetris.start_game();
+            }
+            if shape % 10 == 0 {
+                tetris.queue_garbage(rng.gen_range(1, 3), rng.gen_range(0, COL_COUNT as i32));
+            }
+            let pending = tetris.get_pending_garbage();
+            // the placement completing the most rows, or else the lowest one
+            let placement = tetris.get_placements(true).into_iter()
+                .max_by_key(|placement| {
+                    let mut game = tetris.clone();
+                    let result = play_path(&mut game, placement);
+                    let top = placement.get_cells().iter().map(|point| point.y).min().unwrap();
+                    (result.rows, top, rng.gen_range(0, 4))
+                })
+                .unwrap();
+            for input in placement.path.iter() {
+                if let Some(result) = tetris.apply_input(*input) {
+                    rows += result.rows as u32;
+                }
+                assert_row_bits_match(&tetris);
+            }
+            garbage_rows += pending.saturating_sub(tetris.get_pending_garbage());
+        }
+        assert!(rows > 20);
+        assert!(garbage_rows > 20);
+    }
+
+    #[test]
+    fn attack_follows_the_guideline_table() {
+        let table = AttackTable::guideline();
+        // rows, T-spin, combo, back to back, perfect clear, attack
+        let cases = [
+            (0, TSpin::None, 0, false, false, 0),
+            (1, TSpin::None, 0, false, false, 0),
+            (2, TSpin::None, 0, false, false, 1),
+            (3, TSpin::None, 0, false, false, 2),
+            (4, TSpin::None, 0, false, false, 4),
+            (4, TSpin::None, 0, true, false, 5),
+            (1, TSpin::Mini, 0, false, false, 0),
+            (2, TSpin::Mini, 0, false, false, 1),
+            (1, TSpin::Full, 0, false, false, 2),
+            (2, TSpin::Full, 0, false, false, 4),
+            (2, TSpin::Full, 0, true, false, 5),
+            (3, TSpin::Full, 0, false, false, 6),
+            (1, TSpin::None, 1, false, false, 0),
+            (1, TSpin::None, 2, false, false, 1),
+            (1, TSpin::None, 5, false, false, 2),
+            (2, TSpin::None, 9, false, false, 5),
+            (1, TSpin::None, 11, false, false, 5),
+            (1, TSpin::None, 40, false, false, 5),
+            (0, TSpin::None, 5, false, false, 0),
+            (1, TSpin::None, 0, false, true, 10),
+            (4, TSpin::None, 0, false, true, 14),
+        ];
+        for &(rows, t_spin, combo, back_to_back, perfect_clear, attack) in cases.iter() {
+            assert_eq!(table.attack(rows, t_spin, combo, back_to_back, perfect_clear), attack,
+                "{} rows, {:?}, combo {}, back to back {}, perfect clear {}", rows, t_spin, combo, back_to_back, perfect_clear);
+        }
+    }
+
+    #[test]
+    fn attack_follows_the_tetrio_table() {
+        let table = AttackTable::tetrio();
+        let cases = [
+            (1, TSpin::None, 0, false, false, 0),
+            (4, TSpin::None, 0, false, false, 4),
+            (4, TSpin::None, 0, true, false, 5),
+            (2, TSpin::Full, 0, false, false, 4),
+            // combos multiply the attack
+            (4, TSpin::None, 2, false, false, 6),
+            (2, TSpin::None, 4, false, false, 2),
+            (2, TSpin::Full, 1, true, false, 6),
+            // rows that send nothing still send a little with a long combo
+            (1, TSpin::None, 1, false, false, 0),
+            (1, TSpin::None, 4, false, false, 1),
+            (1, TSpin::None, 10, false, false, 2),
+            (4, TSpin::None, 0, false, true, 14),
+        ];
+        for &(rows, t_spin, combo, back_to_back, perfect_clear, attack) in cases.iter() {
+            assert_eq!(table.attack(rows, t_spin, combo, back_to_back, perfect_clear), attack,
+                "{} rows, {:?}, combo {}, back to back {}, perfect clear {}", rows, t_spin, combo, back_to_back, perfect_clear);
+        }
+    }
+
+    #[test]
+    fn attack_tables_are_found_by_name() {
+        assert!(AttackTable::from_name("guideline").is_some());
+        assert!(matches!(AttackTable::from_name("tetrio").unwrap().combo, ComboAttack::Multiplier(_)));
+        assert!(AttackTable::from_name("tgm").is_none());
+    }
+
+    #[test]
+    fn lock_results_track_combos_and_back_to_backs() {
+        let mut tetris = with_board(6, &["X........."]);
+        let cells = tetris.shape;
+        // rows, T-spin, combo, back to back, attack
+        let locks = [
+            (1, TSpin::None, 0, false, 0),
+            (1, TSpin::None, 1, false, 0),
+            (2, TSpin::None, 2, false, 2),
+            (0, TSpin::None, 0, false, 0),
+            (4, TSpin::None, 0, false, 4),
+            (2, TSpin::Full, 1, true, 5),
+            (4, TSpin::None, 2, true, 6),
+            (1, TSpin::None, 3, false, 1),
+            // the single broke the chain of difficult clears
+            (4, TSpin::None, 4, false, 6),
+        ];
+        let mut total = 0;
+        for &(rows, t_spin, combo, back_to_back, attack) in locks.iter() {
+            let result = tetris.lock_result(rows, t_spin, 0, cells);
+            assert_eq!((result.combo, result.back_to_back, result.attack), (combo, back_to_back, attack),
+                "{} rows, {:?}", rows, t_spin);
+            assert!(!result.perfect_clear);
+            total += attack;
+        }
+        assert_eq!(tetris.get_attack(), total);
+    }
+
+    #[test]
+    fn clearing_the_whole_board_is_a_perfect_clear() {
+        let rows = ["XXXXXXXXX.", "XXXXXXXXX.", "XXXXXXXXX.", "XXXXXXXXX."];
+        let mut tetris = with_board(6, &rows);
+        let placement = tetris.get_placements(false).into_iter()
+            .find(|placement| placement.get_cells().iter().all(|point| point.x == 9))
+            .unwrap();
+        let result = play_path(&mut tetris, &placement);
+        assert_eq!(result.rows, 4);
+        assert!(result.perfect_clear);
+        assert_eq!(result.attack, 14);
+
+        // the same tetris with a cell left over isn't one
+        let mut tetris = with_board(6, &["X.........", "XXXXXXXXX.", "XXXXXXXXX.", "XXXXXXXXX.", "XXXXXXXXX."]);
+        let result = play_path(&mut tetris, &placement);
+        assert_eq!(result.rows, 4);
+        assert!(!result.perfect_clear);
+        assert_eq!(result.attack, 4);
+    }
+
+    #[test]
+    fn rows_sent_cancel_queued_garbage_oldest_first() {
+        let mut tetris = with_board(6, &[]);
+        tetris.queue_garbage(3, 2);
+        tetris.queue_garbage(2, 5);
+        assert_eq!(tetris.get_pending_garbage(), 5);
+        // a partial cancel leaves the rest of the oldest garbage
+        assert_eq!(tetris.cancel_garbage(2), 0);
+        assert_eq!(tetris.get_garbage_queue().iter().map(|garbage| (garbage.rows, garbage.hole_col)).collect::<Vec<_>>(),
+            vec![(1, 2), (2, 5)]);
+        // cancelling more than is queued leaves rows to send
+        assert_eq!(tetris.cancel_garbage(4), 1);
+        assert_eq!(tetris.get_pending_garbage(), 0);
+        assert_eq!(tetris.cancel_garbage(3), 3);
+
+        tetris.queue_garbage(2, 0);
+        assert_eq!(tetris.cancel_garbage(2), 0);
+        assert!(tetris.get_garbage_queue().is_empty());
+        tetris.queue_garbage(0, 0);
+        assert!(tetris.get_garbage_queue().is_empty());
+        tetris.end_game();
+        tetris.queue_garbage(1, 0);
+        assert!(tetris.get_garbage_queue().is_empty());
+    }
+
+    #[test]
+    fn garbage_is_held_back_while_rows_are_completed() {
+        let mut tetris = with_board(6, &["XXXXXXXXX."]);
+        tetris.queue_garbage(2, 3);
+        let placement = tetris.get_placements(false).into_iter()
+            .find(|placement| placement.get_cells().iter().all(|point| point.x == 9))
+            .unwrap();
+        let result = play_path(&mut tetris, &placement);
+        assert_eq!(result.rows, 1);
+        assert_eq!(tetris.get_pending_garbage(), 2);
+        assert_eq!(tetris.get_row_bits()[21], 1 << 9);
+        // the next shape that completes nothing lets it in
+        let result = tetris.hard_drop().unwrap();
+        assert_eq!(result.rows, 0);
+        assert_eq!(tetris.get_pending_garbage(), 0);
+        assert!(!tetris.get_game_over());
+    }
+
+    #[test]
+    fn garbage_rows_push_the_board_up_with_a_hole() {
+        let mut tetris = with_board(SQUARE_SHAPE_INDEX, &["XX........"]);
+        tetris.queue_garbage(2, 7);
+        tetris.queue_garbage(1, 0);
+        tetris.set_col(1);
+        tetris.hard_drop();
+        // the newest garbage is at the bottom, under the older rows
+        assert_eq!(tetris.get_row_bits()[21], FULL_ROW & !1);
+        assert_eq!(tetris.get_row_bits()[20], FULL_ROW & !(1 << 7));
+        assert_eq!(tetris.get_row_bits()[19], FULL_ROW & !(1 << 7));
+        assert_eq!(tetris.get_row_bits()[18], 0b11);
+        for row in 19..ROW_COUNT as i32 {
+            let hole_col = if row == 21 { 0 } else { 7 };
+            for col in 0..COL_COUNT as i32 {
+                let cell = tetris.get_grid_cell(col, row);
+                if col == hole_col {
+                    assert!(cell.cell_type != GridCellType::Fixed);
+                } else {
+                    assert!(cell.cell_type == GridCellType::Fixed && cell.shape_index == GARBAGE_SHAPE_INDEX);
+                }
+            }
+        }
+        assert_row_bits_match(&tetris);
+    }
+
+    #[test]
+    fn garbage_pushing_the_stack_out_of_the_top_ends_the_game() {
+        let mut tetris = with_board(SQUARE_SHAPE_INDEX, &["XX........", "XX........", "XX........"]);
+        tetris.queue_garbage(20, 5);
+        tetris.hard_drop();
+        assert!(tetris.get_game_over());
+
+        // the same garbage on a lower stack still fits
+        let mut tetris = with_board(SQUARE_SHAPE_INDEX, &["XX........"]);
+        tetris.queue_garbage(19, 5);
+        tetris.set_col(8);
+        tetris.hard_drop();
+        assert!(!tetris.get_game_over());
+        assert_eq!(tetris.get_row_bits()[1], 0b1_1000_0000);
+        assert_eq!(tetris.get_row_bits()[2], 0b1_1000_0011);
+    }
+
+    /// Returns the row bits of the given rows at the bottom of the board, where 'X' is filled
+    fn rows_from(rows: &[&str]) -> [u16; ROW_COUNT as usize] {
+        let mut result = [0; ROW_COUNT as usize];
+        let top = ROW_COUNT as usize - rows.len();
+        for (y, text) in rows.iter().enumerate() {
+            for (x, character) in text.chars().enumerate() {
+                if character == 'X' {
+                    result[top + y] |= 1 << x;
+                }
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn board_analysis_of_an_empty_board() {
+        let analysis = BoardAnalysis::new(&[0; ROW_COUNT as usize]);
+        assert_eq!(analysis.column_heights(), [0; COL_COUNT as usize]);
+        assert_eq!(analysis.bumpiness(), 0);
+        assert_eq!(analysis.hole_count(), 0);
+        assert_eq!(analysis.covered_cells(), 0);
+        assert_eq!(analysis.well_depths(), [0; COL_COUNT as usize]);
+        assert_eq!(analysis.well_sum(), 0);
+        // the walls on either side of every row, and the floor under every column
+        assert_eq!(analysis.row_transitions(), 2 * ROW_COUNT as u32);
+        assert_eq!(analysis.column_transitions(), COL_COUNT as u32);
+        assert_eq!(analysis.max_clearable_lines(), 0);
+    }
+
+    #[test]
+    fn board_analysis_of_a_fixed_board() {
+        let analysis = BoardAnalysis::new(&rows_from(&[
+            "....X.....",
+            "X..XX.....",
+            "X.X.X...X.",
+            "XXX.XXX.XX",
+        ]));
+        assert_eq!(analysis.column_heights(), [3, 1, 2, 3, 4, 1, 1, 0, 2, 1]);
+        assert_eq!(analysis.column_height(4), 4);
+        assert_eq!(analysis.bumpiness(), 12);
+        assert_eq!(analysis.holes(), vec![Point { x: 3, y: 20 }, Point { x: 3, y: 21 }]);
+        assert_eq!(analysis.hole_count(), 2);
+        assert_eq!(analysis.covered_cells(), 1);
+        assert_eq!(analysis.well_depths(), [0, 1, 0, 0, 0, 0, 0, 1, 0, 1]);
+        // one cell deep in columns 1, 7 and 9, and two deep in column 3
+        assert_eq!(analysis.well_sum(), 6);
+        assert_eq!(analysis.row_transitions(), 2 * 18 + 4 + 4 + 8 + 4);
+        assert_eq!(analysis.column_transitions(), 12);
+        assert_eq!(analysis.max_clearable_lines(), 0);
+    }
+
+    #[test]
+    fn max_clearable_lines_drops_every_shape_and_rotation() {
+        let tetris_ready = rows_from(&["XXXXXXXXX.", "XXXXXXXXX.", "XXXXXXXXX.", "XXXXXXXXX.", "XXXXXXXXX."]);
+        assert_eq!(BoardAnalysis::new(&tetris_ready).max_clearable_lines(), 4);
+        // a T pointing down
+        let t_slot = rows_from(&["XXX...XXXX", "XXXX.XXXXX"]);
+        assert_eq!(BoardAnalysis::new(&t_slot).max_clearable_lines(), 2);
+        // the same slot covered over can't be reached by dropping straight down
+        let covered = rows_from(&["XXXXXX..X.", "XXX...XXXX", "XXXX.XXXXX"]);
+        assert_eq!(BoardAnalysis::new(&covered).max_clearable_lines(), 0);
+    }
+
+    #[test]
+    fn paused_games_ignore_inputs_drops_and_ticks() {
+        let mut tetris = with_board(SQUARE_SHAPE_INDEX, &[]);
+        tetris.set_paused(true);
+        let (row, col) = (tetris.row, tetris.col);
+        assert!(tetris.apply_input(Input::Left).is_none());
+        assert!(tetris.hard_drop().is_none());
+        assert!(tetris.tick().is_none());
+        assert_eq!((tetris.row, tetris.col), (row, col));
+        assert!(tetris.get_row_bits().iter().all(|&bits| bits == 0));
+
+        tetris.set_paused(false);
+        assert!(tetris.hard_drop().is_some());
+        assert_eq!(tetris.get_row_bits()[ROW_COUNT as usize - 1], 0b11_0000);
+    }
+}